@@ -0,0 +1,691 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `xddcc` 命令行。
+//!
+//! 本库不负责登录，`xddcc` 可执行文件使用前端保存在配置目录中的会话，见 [`crate::config`].
+//! 没有可用的会话时，`stream`, `view`, `download`, `export playlist --room` 与 `relay`
+//! 等无需登录的命令仍可使用，其余命令返回 [`CliError::NoSession`].
+//! 自行管理会话的前端可以调用 [`run`].
+
+use crate::{
+    config::{Config, ConfigError},
+    download::{download_stream, DownloadError, DownloadOptions},
+    feed::{Feed, FeedFormat},
+    html::MultiView,
+    ics::{fetch_semester, render_calendar},
+    lesson::Lesson,
+    manifest::{DownloadManifest, ManifestFormat, DEFAULT_TEMPLATE},
+    output::{OutputError, OutputFormat},
+    playlist::{PlayerPlaylist, PlaylistFormat},
+    ts::{concat_download, ConcatOptions},
+    Live, LogProgress, PairVec, Room, ServerSession, SessionPool, StreamKind, TermError,
+};
+use clap::{Parser, Subcommand};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "xddcc", version, about = "XiDian Distant Courses Collector")]
+pub struct Cli {
+    /// 输出格式，默认根据输出文件的扩展名推断，否则取配置文件中的格式。
+    #[arg(short, long, global = true)]
+    format: Option<OutputFormat>,
+    /// 输出文件，默认输出到标准输出。
+    #[arg(short, long, global = true)]
+    output: Option<PathBuf>,
+    /// 配置文件，默认位置见 `xddcc::config`.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 扫描课表，获取所有教室及其设备码。
+    Rooms,
+    /// 获取各会话当前的直播。
+    Now {
+        /// 获取上一节课的直播。
+        #[arg(short, long)]
+        previous: bool,
+    },
+    /// 列出课程的所有课次。
+    Lessons {
+        live_id: i64,
+        /// 改为获取各课次的录播地址。
+        #[arg(short, long)]
+        recordings: bool,
+    },
+    /// 获取教室的直播地址。
+    Stream {
+        /// 教室的设备码，指定了 `--rooms` 时为教室名称。
+        room: String,
+        /// 由 `rooms` 命令以 JSON 格式导出的教室列表。
+        #[arg(long)]
+        rooms: Option<PathBuf>,
+    },
+    /// 生成同时观看多个教室直播的 HTML 页面。
+    View {
+        /// 教室的设备码，指定了 `--rooms` 时为教室名称。
+        #[arg(required = true)]
+        room: Vec<String>,
+        /// 由 `rooms` 命令以 JSON 格式导出的教室列表。
+        #[arg(long)]
+        rooms: Option<PathBuf>,
+        /// 每个教室显示的视频，以 `,` 分隔，默认取配置文件中的视频。
+        #[arg(short, long, value_delimiter = ',')]
+        kinds: Vec<StreamKind>,
+        /// 每行的列数。
+        #[arg(long)]
+        columns: Option<usize>,
+        /// HLS 中继的地址，如 `http://127.0.0.1:8081`. 指定后页面使用中继的固定地址，不再获取直播地址。
+        #[arg(long)]
+        relay: Option<String>,
+        /// hls.js 的地址，默认从 jsDelivr 加载，离线使用时可改为本地文件。
+        #[arg(long)]
+        hls_js: Option<String>,
+        #[arg(long, default_value = "教室直播")]
+        title: String,
+    },
+    /// 下载课次的录播，输出下载完成的分片文件。
+    Download {
+        lesson_id: i64,
+        /// 默认为配置文件中的第一种视频。
+        #[arg(short, long)]
+        kind: Option<StreamKind>,
+        /// 分片的保存目录，中断后以相同的目录重新运行即可继续下载。
+        #[arg(short, long)]
+        dir: PathBuf,
+        /// 下载完成后将分片拼接为该文件。
+        #[arg(long)]
+        concat: Option<PathBuf>,
+        /// 同时下载的分片数，默认取配置文件中的值。
+        #[arg(long)]
+        concurrency: Option<usize>,
+    },
+    /// 导出课表或录播订阅源。
+    #[command(subcommand)]
+    Export(Export),
+    /// 启动本地 HTTP 服务，接口见 `xddcc::server`.
+    #[cfg(feature = "server")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// 启动本地 HLS 中继，地址见 `xddcc::relay`.
+    #[cfg(feature = "server")]
+    Relay {
+        #[arg(long, default_value = "127.0.0.1:8081")]
+        listen: String,
+    },
+}
+#[derive(Subcommand, Debug)]
+pub enum Export {
+    /// 以 iCalendar 格式导出本学期的直播课表。
+    Calendar {
+        #[arg(long, default_value_t = 1)]
+        from_week: i64,
+        #[arg(long, default_value_t = 20)]
+        to_week: i64,
+    },
+    /// 导出课程录播的 RSS 订阅源。
+    Feed {
+        live_id: i64,
+        #[arg(long, default_value = "课程录播")]
+        title: String,
+        /// 默认为配置文件中的第一种视频。
+        #[arg(short, long)]
+        kind: Option<StreamKind>,
+        /// 改为导出 Atom 订阅源。
+        #[arg(long)]
+        atom: bool,
+        /// 订阅源指向的网页地址，默认为录播平台的首页。
+        #[arg(long)]
+        link: Option<String>,
+    },
+    /// 导出课程全部录播的下载清单，供 aria2 等下载工具使用。
+    ///
+    /// 录播地址通常是 HLS 播放列表，需要支持 HLS 的下载工具，见 `xddcc::manifest`.
+    Manifest {
+        live_id: i64,
+        /// 文件名模板中的 `{course}`.
+        #[arg(long, default_value = "课程录播")]
+        title: String,
+        /// 以 `,` 分隔，默认取配置文件中的视频。
+        #[arg(short, long, value_delimiter = ',')]
+        kinds: Vec<StreamKind>,
+        /// 文件名模板，可用 `{course}`, `{lesson_id}`, `{date}`, `{time}` 与 `{stream}`.
+        #[arg(long, default_value = DEFAULT_TEMPLATE)]
+        template: String,
+        /// 导出每行一个地址的纯文本列表，而非 aria2 的输入文件。
+        #[arg(long)]
+        urls: bool,
+        /// 附带的请求头，如 `Referer: http://...`, 可指定多次。
+        #[arg(short = 'H', long = "header")]
+        headers: Vec<String>,
+        /// 为各地址附带会话中与其域名和路径匹配的 Cookie. Cookie 以明文写入清单。
+        #[arg(long)]
+        cookies: bool,
+        /// 文件名与课次的 JSON 对照表的路径，指定了 `-o` 时默认为清单路径加上 `.json`.
+        #[arg(long)]
+        sidecar: Option<PathBuf>,
+    },
+    /// 导出供播放器使用的播放列表，格式默认根据输出文件的扩展名推断，否则为 M3U.
+    Playlist {
+        /// 导出该课程的全部录播。
+        #[arg(long, conflicts_with = "room", required_unless_present = "room")]
+        course: Option<i64>,
+        /// 导出这些教室的直播，为设备码，指定了 `--rooms` 时为教室名称。
+        #[arg(long, num_args = 1..)]
+        room: Vec<String>,
+        /// 由 `rooms` 命令以 JSON 格式导出的教室列表。
+        #[arg(long)]
+        rooms: Option<PathBuf>,
+        /// 以 `,` 分隔，默认取配置文件中的视频。
+        #[arg(short, long, value_delimiter = ',')]
+        kinds: Vec<StreamKind>,
+        /// 按视频类型分组。
+        #[arg(long)]
+        group: bool,
+        /// 导出为 XSPF.
+        #[arg(long)]
+        xspf: bool,
+        #[arg(long, default_value = "课程录播")]
+        title: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    /// 命令需要会话，但没有可用的会话。
+    NoSession,
+    RoomNotFound(String),
+    /// 无法确定当前的学期或其开始日期。
+    Term(TermError),
+    Config(ConfigError),
+    Request(Box<ureq::Error>),
+    Download(DownloadError),
+    Output(OutputError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+impl Display for CliError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::NoSession => f.write_str("该命令需要登录，但没有可用的会话"),
+            CliError::RoomNotFound(room) => write!(f, "没有找到教室 `{room}`"),
+            CliError::Term(e) => write!(f, "{e}"),
+            CliError::Config(e) => write!(f, "{e}"),
+            CliError::Request(e) => write!(f, "请求出错：{e}"),
+            CliError::Download(e) => write!(f, "{e}"),
+            CliError::Output(e) => write!(f, "{e}"),
+            CliError::Io(e) => write!(f, "读写出错：{e}"),
+            CliError::Json(e) => write!(f, "json 解析出错：{e}"),
+        }
+    }
+}
+impl std::error::Error for CliError {}
+impl From<ConfigError> for CliError {
+    fn from(e: ConfigError) -> Self {
+        CliError::Config(e)
+    }
+}
+impl From<TermError> for CliError {
+    fn from(e: TermError) -> Self {
+        CliError::Term(e)
+    }
+}
+impl From<Box<ureq::Error>> for CliError {
+    fn from(e: Box<ureq::Error>) -> Self {
+        CliError::Request(e)
+    }
+}
+impl From<DownloadError> for CliError {
+    fn from(e: DownloadError) -> Self {
+        CliError::Download(e)
+    }
+}
+impl From<OutputError> for CliError {
+    fn from(e: OutputError) -> Self {
+        CliError::Output(e)
+    }
+}
+impl From<std::io::Error> for CliError {
+    fn from(e: std::io::Error) -> Self {
+        CliError::Io(e)
+    }
+}
+impl From<serde_json::Error> for CliError {
+    fn from(e: serde_json::Error) -> Self {
+        CliError::Json(e)
+    }
+}
+
+impl Cli {
+    /// 读取 `--config` 指定的或默认位置的配置文件，并应用环境变量。
+    pub fn load_config(&self) -> Result<Config, ConfigError> {
+        match &self.config {
+            Some(path) => {
+                let mut config = Config::load(path)?;
+                config.apply_env()?;
+                Ok(config)
+            }
+            None => Config::load_default(),
+        }
+    }
+    /// 未指定 `--format` 时，根据输出文件的扩展名推断，否则为 `default`.
+    fn output_format(&self, default: OutputFormat) -> OutputFormat {
+        self.format
+            .or_else(|| self.output.as_deref().and_then(OutputFormat::from_path))
+            .unwrap_or(default)
+    }
+    fn emit<S: Serialize>(&self, contents: &S) -> Result<(), CliError> {
+        let format = self.format.unwrap_or_default();
+        Ok(crate::out(contents, self.output.clone(), format)?)
+    }
+    /// 输出已经生成好的文本，如日历与订阅源。
+    fn emit_text(&self, text: &str) -> Result<(), CliError> {
+        match &self.output {
+            Some(path) => crate::output::write_atomic(path, text.as_bytes())?,
+            None => print!("{text}"),
+        }
+        Ok(())
+    }
+}
+/// 读取由 `rooms` 命令以 JSON 格式导出的教室列表。
+fn load_rooms(path: Option<&Path>) -> Result<Option<PairVec<String, String>>, CliError> {
+    match path {
+        Some(path) => Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?)),
+        None => Ok(None),
+    }
+}
+/// 没有教室列表时，`room` 即为设备码。
+fn device_code(room: &str, rooms: Option<&PairVec<String, String>>) -> Result<String, CliError> {
+    match rooms {
+        Some(rooms) => rooms
+            .get(room)
+            .cloned()
+            .ok_or_else(|| CliError::RoomNotFound(room.to_owned())),
+        None => Ok(room.to_owned()),
+    }
+}
+fn first_session(pool: &SessionPool) -> Result<&ServerSession, CliError> {
+    pool.next().ok_or(CliError::NoSession)
+}
+/// 以 `config` 中的默认值执行命令。
+pub fn run(mut cli: Cli, config: &Config, pool: &SessionPool) -> Result<(), CliError> {
+    cli.format = Some(cli.output_format(config.output().format()));
+    let cli = &cli;
+    let default_kind = config
+        .download()
+        .kinds()
+        .first()
+        .copied()
+        .unwrap_or(StreamKind::PptVideo);
+    let multi = LogProgress::default();
+    match &cli.command {
+        Command::Rooms => {
+            first_session(pool)?;
+            let rooms = Room::get_all_rooms_with(pool, config.scan(), &multi);
+            cli.emit(&PairVec::from(rooms))
+        }
+        Command::Now { previous } => {
+            first_session(pool)?;
+            let jie = config.timetable().now_to_jie(*previous);
+            cli.emit(&PairVec::from(Live::get_lives_by_jie(pool, jie, &multi)?))
+        }
+        Command::Lessons {
+            live_id,
+            recordings: true,
+        } => {
+            let recordings = Lesson::get_recording_lives(first_session(pool)?, *live_id, &multi)?;
+            cli.emit(&PairVec::from(recordings))
+        }
+        Command::Lessons {
+            live_id,
+            recordings: false,
+        } => {
+            first_session(pool)?;
+            let lessons = pool.with_failover(|session| Lesson::get_lessons(session, *live_id))?;
+            cli.emit(&lessons)
+        }
+        Command::Stream { room, rooms } => {
+            let device_code = device_code(room, load_rooms(rooms.as_deref())?.as_ref())?;
+            let video_path = crate::get_live_video_path(&pool.anonymous_client(), &device_code)?;
+            cli.emit(&video_path)
+        }
+        Command::View {
+            room,
+            rooms,
+            kinds,
+            columns,
+            relay,
+            hls_js,
+            title,
+        } => {
+            let rooms = load_rooms(rooms.as_deref())?;
+            let kinds = if kinds.is_empty() {
+                config.download().kinds()
+            } else {
+                kinds.as_slice()
+            };
+            let mut view = MultiView::new(title.as_str());
+            if let Some(columns) = columns {
+                view = view.columns(*columns);
+            }
+            if let Some(hls_js) = hls_js {
+                view = view.player_script(hls_js.as_str());
+            }
+            let client = pool.anonymous_client();
+            for room in room {
+                let device_code = device_code(room, rooms.as_ref())?;
+                match relay {
+                    // 路径格式见 `xddcc::relay`.
+                    Some(relay) => {
+                        let relay = relay.trim_end_matches('/');
+                        for kind in kinds {
+                            view.add(
+                                format!("{room} {kind}"),
+                                format!("{relay}/live/{device_code}/{kind}.m3u8"),
+                            );
+                        }
+                    }
+                    None => {
+                        let video_path = crate::get_live_video_path(&client, &device_code)?;
+                        if view.add_video_path(room, &video_path, kinds) == 0 {
+                            warn!("教室 `{room}` 没有可用的直播。");
+                        }
+                    }
+                }
+            }
+            cli.emit_text(&view.render())
+        }
+        Command::Download {
+            lesson_id,
+            kind,
+            dir,
+            concat,
+            concurrency,
+        } => {
+            let client = pool.anonymous_client();
+            let video_path = crate::get_recording_live_video_path(&client, *lesson_id)?;
+            let kind = kind.unwrap_or(default_kind);
+            let options = DownloadOptions {
+                concurrency: concurrency.unwrap_or(config.download().concurrency()),
+                ..config.download().options()
+            };
+            let files = download_stream(&client, &video_path, kind, dir, &options, &multi)?;
+            if let Some(concat) = concat {
+                let options = ConcatOptions {
+                    rewrite_timestamps: true,
+                };
+                concat_download(dir, concat, options)?;
+            }
+            cli.emit(&files)
+        }
+        Command::Export(Export::Calendar { from_week, to_week }) => {
+            let session = first_session(pool)?;
+            let (term_year, term, _) = crate::term_year_detail(session)?;
+            let semester_start = crate::term_begin_date(session, term_year, term)?;
+            let entries = fetch_semester(session, term_year, term, *from_week..=*to_week)?;
+            let calendar = render_calendar(
+                &format!("{term_year}-{term} 学期直播课表"),
+                &entries,
+                semester_start,
+                config.timetable(),
+            );
+            cli.emit_text(&calendar)
+        }
+        Command::Export(Export::Feed {
+            live_id,
+            title,
+            kind,
+            atom,
+            link,
+        }) => {
+            let recordings = Lesson::get_recording_lives(first_session(pool)?, *live_id, &multi)?;
+            let link = link
+                .clone()
+                .unwrap_or_else(|| format!("http://{}/", pool.server().host()));
+            let mut feed = Feed::new(*live_id, title.as_str(), link);
+            feed.add_recordings(&recordings, kind.unwrap_or(default_kind));
+            let format = if *atom {
+                FeedFormat::Atom
+            } else {
+                FeedFormat::Rss
+            };
+            cli.emit_text(&feed.render(format))
+        }
+        Command::Export(Export::Manifest {
+            live_id,
+            title,
+            kinds,
+            template,
+            urls,
+            headers,
+            cookies,
+            sidecar,
+        }) => {
+            let kinds = if kinds.is_empty() {
+                config.download().kinds()
+            } else {
+                kinds.as_slice()
+            };
+            let session = first_session(pool)?;
+            let mut manifest = DownloadManifest::new(title.as_str()).template(template.as_str());
+            for header in headers {
+                match header.split_once(':') {
+                    Some((name, value)) => {
+                        manifest = manifest.header(name.trim(), value.trim());
+                    }
+                    None => warn!("忽略无效的请求头 `{header}`."),
+                }
+            }
+            if *cookies {
+                manifest = manifest.cookies(session);
+            }
+            let lessons = Lesson::get_lessons(session, *live_id)?;
+            let recordings = Lesson::get_recording_lives(session, *live_id, &multi)?;
+            let count = manifest.add_recordings(&lessons, &recordings, kinds)?;
+            info!("共 {count} 个文件。");
+            let playlists = manifest
+                .entries()
+                .iter()
+                .filter(|e| e.is_playlist())
+                .count();
+            if playlists > 0 && !*urls {
+                warn!("其中 {playlists} 个地址为 HLS 播放列表，aria2 只会下载播放列表本身。");
+            }
+            let format = if *urls {
+                ManifestFormat::UrlList
+            } else {
+                ManifestFormat::Aria2
+            };
+            cli.emit_text(&manifest.render(format))?;
+            let sidecar = sidecar.clone().or_else(|| {
+                cli.output.as_ref().map(|output| {
+                    let mut path = output.clone().into_os_string();
+                    path.push(".json");
+                    PathBuf::from(path)
+                })
+            });
+            if let Some(sidecar) = sidecar {
+                crate::output::write_atomic(&sidecar, manifest.sidecar().as_bytes())?;
+            }
+            Ok(())
+        }
+        Command::Export(Export::Playlist {
+            course,
+            room,
+            rooms,
+            kinds,
+            group,
+            xspf,
+            title,
+        }) => {
+            let kinds = if kinds.is_empty() {
+                config.download().kinds()
+            } else {
+                kinds.as_slice()
+            };
+            let mut playlist = PlayerPlaylist::new(title.as_str()).group_by_stream(*group);
+            if let Some(live_id) = course {
+                let recordings =
+                    Lesson::get_recording_lives(first_session(pool)?, *live_id, &multi)?;
+                playlist.add_recordings(title, &recordings, kinds);
+            }
+            let rooms = load_rooms(rooms.as_deref())?;
+            let client = pool.anonymous_client();
+            for room in room {
+                let device_code = device_code(room, rooms.as_ref())?;
+                let video_path = crate::get_live_video_path(&client, &device_code)?;
+                if playlist.add_video_path(room, &video_path, kinds) == 0 {
+                    warn!("教室 `{room}` 没有可用的直播。");
+                }
+            }
+            let format = if *xspf {
+                PlaylistFormat::Xspf
+            } else {
+                cli.output
+                    .as_deref()
+                    .and_then(PlaylistFormat::from_path)
+                    .unwrap_or_default()
+            };
+            cli.emit_text(&playlist.render(format))
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { listen } => {
+            use crate::server::{ApiServer, ServerOptions};
+            first_session(pool)?;
+            let options = ServerOptions {
+                scan: config.scan().clone(),
+                timetable: config.timetable().clone(),
+                ..Default::default()
+            };
+            let server = ApiServer::new(pool.clone(), options);
+            Ok(server.serve(listen.as_str(), &crate::CancellationHandle::new())?)
+        }
+        #[cfg(feature = "server")]
+        Command::Relay { listen } => {
+            let options = crate::relay::RelayOptions {
+                server: config.server().clone(),
+                ..Default::default()
+            };
+            let relay = crate::relay::HlsRelay::new(options);
+            Ok(relay.serve(listen.as_str(), &crate::CancellationHandle::new())?)
+        }
+    }
+}
+/// 解析命令行参数，以配置文件中保存的会话执行命令，供 `xddcc` 可执行文件使用。
+pub fn main() -> ExitCode {
+    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .try_init();
+    let cli = Cli::parse();
+    let result = cli
+        .load_config()
+        .map_err(CliError::from)
+        .and_then(|config| {
+            let pool = config.session_pool()?;
+            run(cli, &config, &pool)
+        });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("错误：{e}.");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cli = Cli::try_parse_from(["xddcc", "lessons", "42", "-r", "-o", "a.csv"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Lessons {
+                live_id: 42,
+                recordings: true
+            }
+        ));
+        let cli = Cli::try_parse_from([
+            "xddcc", "-f", "toml", "export", "playlist", "--room", "a", "b",
+        ])
+        .unwrap();
+        assert_eq!(cli.format, Some(OutputFormat::Toml));
+        assert!(
+            matches!(&cli.command, Command::Export(Export::Playlist { room, .. }) if room == &["a", "b"])
+        );
+        // `--course` 与 `--room` 互斥，且必须指定其一。
+        assert!(Cli::try_parse_from(["xddcc", "export", "playlist"]).is_err());
+        assert!(Cli::try_parse_from([
+            "xddcc", "export", "playlist", "--course", "1", "--room", "a"
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from(["xddcc", "download", "1"]).is_err());
+        assert!(Cli::try_parse_from(["xddcc"]).is_err());
+    }
+    #[test]
+    fn test_parse_all() {
+        for args in [
+            &["xddcc", "rooms"][..],
+            &["xddcc", "now", "-p"],
+            &["xddcc", "lessons", "1"],
+            &["xddcc", "stream", "B-203"],
+            &["xddcc", "view", "a", "b"],
+            &["xddcc", "download", "1", "-d", "out"],
+            &["xddcc", "export", "calendar"],
+            &["xddcc", "export", "feed", "1"],
+            &["xddcc", "export", "manifest", "1", "--cookies"],
+            &["xddcc", "export", "playlist", "--course", "1"],
+        ] {
+            assert!(Cli::try_parse_from(args).is_ok(), "{args:?}");
+        }
+        #[cfg(feature = "server")]
+        for args in [&["xddcc", "serve"][..], &["xddcc", "relay"]] {
+            assert!(Cli::try_parse_from(args).is_ok(), "{args:?}");
+        }
+    }
+    #[test]
+    fn test_no_session() {
+        let cli = Cli::try_parse_from(["xddcc", "lessons", "1"]).unwrap();
+        let result = run(
+            cli,
+            &Config::default(),
+            &SessionPool::new(Vec::<cxlib_types::Session>::new()),
+        );
+        assert!(matches!(result, Err(CliError::NoSession)));
+    }
+    #[test]
+    fn test_output_format() {
+        let cli = Cli::try_parse_from(["xddcc", "stream", "a", "-o", "out/rooms.CSV"]).unwrap();
+        assert_eq!(cli.output.as_deref(), Some(Path::new("out/rooms.CSV")));
+        assert_eq!(cli.output_format(OutputFormat::Json), OutputFormat::Csv);
+        let cli =
+            Cli::try_parse_from(["xddcc", "stream", "a", "-o", "rooms.md", "-f", "json"]).unwrap();
+        assert_eq!(cli.output_format(OutputFormat::Toml), OutputFormat::Json);
+        let cli = Cli::try_parse_from(["xddcc", "stream", "a", "-o", "rooms"]).unwrap();
+        assert_eq!(cli.output_format(OutputFormat::Toml), OutputFormat::Toml);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::protocol::Server;
+use cxlib_types::Session;
+use std::{
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+use ureq::Agent;
+
+/// 能够发起请求的客户端。
+///
+/// 不需要登录的接口只要求实现该 trait 的类型，[`Session`] 与 [`AnonymousClient`] 均可使用。
+pub trait Client {
+    fn agent(&self) -> &Agent;
+    /// 请求的平台，默认为 [`Server::default`].
+    fn server(&self) -> &Server {
+        crate::protocol::default_server()
+    }
+}
+/// 已登录的客户端，需要登录的接口要求实现该 trait 的类型。
+///
+/// [`Session`] 使用默认的平台，需要其它平台时使用 [`ServerSession`].
+pub trait SessionClient: Client {
+    fn session(&self) -> &Session;
+}
+impl Client for Agent {
+    fn agent(&self) -> &Agent {
+        self
+    }
+}
+impl Client for Session {
+    fn agent(&self) -> &Agent {
+        self
+    }
+}
+impl<C: Client + ?Sized> Client for &C {
+    fn agent(&self) -> &Agent {
+        (**self).agent()
+    }
+    fn server(&self) -> &Server {
+        (**self).server()
+    }
+}
+impl SessionClient for Session {
+    fn session(&self) -> &Session {
+        self
+    }
+}
+impl<C: SessionClient + ?Sized> SessionClient for &C {
+    fn session(&self) -> &Session {
+        (**self).session()
+    }
+}
+/// 使用指定平台的会话，相等性与哈希值同 [`Session`].
+#[derive(Debug, Clone)]
+pub struct ServerSession {
+    session: Session,
+    server: Server,
+}
+impl ServerSession {
+    pub fn new(session: Session, server: Server) -> Self {
+        Self { session, server }
+    }
+}
+impl From<Session> for ServerSession {
+    fn from(session: Session) -> Self {
+        Self::new(session, Server::default())
+    }
+}
+impl Deref for ServerSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.session
+    }
+}
+impl PartialEq for ServerSession {
+    fn eq(&self, other: &Self) -> bool {
+        self.session == other.session
+    }
+}
+impl Eq for ServerSession {}
+impl Hash for ServerSession {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.session.hash(state)
+    }
+}
+impl Client for ServerSession {
+    fn agent(&self) -> &Agent {
+        &self.session
+    }
+    fn server(&self) -> &Server {
+        &self.server
+    }
+}
+impl SessionClient for ServerSession {
+    fn session(&self) -> &Session {
+        &self.session
+    }
+}
+/// 无需任何账号的匿名客户端。
+#[derive(Debug, Clone)]
+pub struct AnonymousClient {
+    agent: Agent,
+    server: Server,
+}
+impl AnonymousClient {
+    pub fn new() -> Self {
+        Self::from(Agent::new_with_defaults())
+    }
+    /// 改为请求 `server`.
+    pub fn with_server(mut self, server: Server) -> Self {
+        self.server = server;
+        self
+    }
+}
+impl Default for AnonymousClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl From<Agent> for AnonymousClient {
+    fn from(agent: Agent) -> Self {
+        Self {
+            agent,
+            server: Server::default(),
+        }
+    }
+}
+impl Client for AnonymousClient {
+    fn agent(&self) -> &Agent {
+        &self.agent
+    }
+    fn server(&self) -> &Server {
+        &self.server
+    }
+}
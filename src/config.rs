@@ -0,0 +1,624 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! TOML 格式的配置文件。
+//!
+//! 默认位于 `$XDG_CONFIG_HOME/xddcc/config.toml`, 未设置 `XDG_CONFIG_HOME` 时为 `~/.config/xddcc/config.toml`,
+//! 也可以由环境变量 `XDDCC_CONFIG` 指定。各项均可省略，省略时取库中原有的默认值。
+//!
+//! ```toml
+//! cache_dir = "/var/cache/xddcc"
+//!
+//! [server]
+//! host = "newesxidian.chaoxing.com"
+//! fid = 16820
+//!
+//! [sessions]
+//! # 保存的会话所在的目录，默认为 `$XDG_DATA_HOME/xddcc/sessions` 或 `~/.local/share/xddcc/sessions`.
+//! dir = "/var/lib/xddcc/sessions"
+//! # 按 uid 或用户名选择会话，为空时使用全部会话。
+//! accounts = ["12345678"]
+//!
+//! [scan]
+//! threads = 32
+//! years = 4
+//! # 每秒最多发出的请求数。
+//! rate_limit = 20
+//!
+//! [download]
+//! concurrency = 4
+//! retries = 3
+//! kinds = ["ppt_video", "teacher_track"]
+//!
+//! # 单位为秒。
+//! [monitor]
+//! poll_interval = 300
+//! max_poll_interval = 3600
+//!
+//! [output]
+//! dir = "/srv/courses"
+//! template = "{live_id}/{date}-{time}.json"
+//! format = "json"
+//!
+//! [[timetable]]
+//! jie = 1
+//! start = "08:30"
+//! end = "10:05"
+//! ```
+//!
+//! 读取后，以下环境变量会覆盖文件中的值：
+//!
+//! | 环境变量 | 配置项 |
+//! | --- | --- |
+//! | `XDDCC_HOST` | `server.host` |
+//! | `XDDCC_FID` | `server.fid` |
+//! | `XDDCC_SESSIONS_DIR` | `sessions.dir` |
+//! | `XDDCC_ACCOUNTS` | `sessions.accounts`, 以 `,` 分隔 |
+//! | `XDDCC_SCAN_THREADS` | `scan.threads` |
+//! | `XDDCC_SCAN_YEARS` | `scan.years` |
+//! | `XDDCC_RATE_LIMIT` | `scan.rate_limit` |
+//! | `XDDCC_CONCURRENCY` | `download.concurrency` |
+//! | `XDDCC_RETRIES` | `download.retries` |
+//! | `XDDCC_STREAM_KINDS` | `download.kinds`, 以 `,` 分隔 |
+//! | `XDDCC_CACHE_DIR` | `cache_dir` |
+//! | `XDDCC_OUTPUT_DIR` | `output.dir` |
+//! | `XDDCC_OUTPUT_FORMAT` | `output.format` |
+//!
+//! # 保存的会话
+//!
+//! 本库不负责登录。登录后的会话由前端以 JSON 格式保存在 `sessions.dir` 中，每个账号一个文件，
+//! 文件名任意，扩展名为 `.json`:
+//!
+//! ```json
+//! { "uid": "12345678", "name": "张三", "cookies": [] }
+//! ```
+//!
+//! 其中 `cookies` 为 [`ureq::CookieJar::save_json`] 导出的内容，见 [`Config::session_pool`].
+
+use crate::{
+    monitor::MonitorOptions,
+    output::{OutputFormat, OutputSink},
+    protocol::Server,
+    AnonymousClient, ScanOptions, SessionPool, StreamKind, Timetable,
+};
+use cxlib_types::Session;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    /// 环境变量的值无法解析。
+    Env {
+        name: &'static str,
+        value: String,
+    },
+    /// 配置项的值超出范围。
+    Invalid {
+        key: &'static str,
+        value: String,
+    },
+}
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "配置文件读取出错：{e}"),
+            ConfigError::Parse(e) => write!(f, "配置文件解析出错：{e}"),
+            ConfigError::Env { name, value } => write!(f, "环境变量 `{name}` 的值 `{value}` 无效"),
+            ConfigError::Invalid { key, value } => write!(f, "配置项 `{key}` 的值 `{value}` 无效"),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+/// 保存的会话，格式见模块文档。
+#[derive(Deserialize, Serialize, Debug)]
+struct StoredSession {
+    uid: String,
+    name: String,
+    cookies: serde_json::Value,
+}
+impl StoredSession {
+    fn load(path: &Path) -> Result<Session, String> {
+        let stored: StoredSession =
+            serde_json::from_slice(&std::fs::read(path).map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())?;
+        let cookies = serde_json::to_vec(&stored.cookies).map_err(|e| e.to_string())?;
+        let session = Session::new(&stored.uid, &stored.name);
+        session
+            .cookie_jar_lock()
+            .load_json(cookies.as_slice())
+            .map_err(|e| e.to_string())?;
+        Ok(session)
+    }
+}
+/// 使用哪些会话。
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SessionsConfig {
+    dir: Option<PathBuf>,
+    accounts: Vec<String>,
+}
+impl SessionsConfig {
+    /// 保存的会话所在的目录，见模块文档。
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.dir
+            .clone()
+            .or_else(|| xdg_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join("sessions")))
+    }
+    pub fn accounts(&self) -> &[String] {
+        &self.accounts
+    }
+    /// 读取 [`SessionsConfig::dir`] 中保存的全部会话，按文件名排序。
+    ///
+    /// 目录不存在时返回空列表；无法读取的文件会被跳过。
+    pub fn load(&self) -> Result<Vec<Session>, ConfigError> {
+        let Some(dir) = self.dir() else {
+            return Ok(Vec::new());
+        };
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut paths = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+        paths.sort();
+        Ok(paths
+            .iter()
+            .filter_map(|path| {
+                StoredSession::load(path)
+                    .map_err(|e| warn!("会话 `{}` 读取出错：{e}.", path.display()))
+                    .ok()
+            })
+            .collect())
+    }
+    /// `accounts` 为空时选择全部会话，否则按 uid 或用户名匹配。
+    pub fn is_selected(&self, session: &Session) -> bool {
+        self.accounts.is_empty()
+            || self
+                .accounts
+                .iter()
+                .any(|a| a == session.uid() || a == session.name())
+    }
+    /// 选中的会话，见 [`Config::session_pool`].
+    pub fn select(&self, sessions: impl IntoIterator<Item = Session>) -> Vec<Session> {
+        sessions
+            .into_iter()
+            .filter(|s| self.is_selected(s))
+            .collect()
+    }
+}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DownloadConfig {
+    concurrency: usize,
+    retries: u32,
+    /// 默认下载或录制的视频。
+    kinds: Vec<StreamKind>,
+}
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            retries: 3,
+            kinds: vec![StreamKind::PptVideo],
+        }
+    }
+}
+impl DownloadConfig {
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+    pub fn kinds(&self) -> &[StreamKind] {
+        &self.kinds
+    }
+    #[cfg(feature = "download")]
+    pub fn options(&self) -> crate::download::DownloadOptions {
+        crate::download::DownloadOptions {
+            concurrency: self.concurrency,
+            retries: self.retries,
+        }
+    }
+    #[cfg(feature = "download")]
+    pub fn record_options(&self) -> crate::record::RecordOptions {
+        crate::record::RecordOptions {
+            retries: self.retries,
+            ..Default::default()
+        }
+    }
+}
+/// 录播查询的间隔，单位为秒，见 [`MonitorOptions`].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct MonitorConfig {
+    poll_interval: u64,
+    max_poll_interval: u64,
+    give_up_after: u64,
+}
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        let options = MonitorOptions::default();
+        Self {
+            poll_interval: options.initial_interval.as_secs(),
+            max_poll_interval: options.max_interval.as_secs(),
+            give_up_after: options.give_up_after.as_secs(),
+        }
+    }
+}
+impl MonitorConfig {
+    pub fn options(&self) -> MonitorOptions {
+        MonitorOptions {
+            initial_interval: Duration::from_secs(self.poll_interval),
+            max_interval: Duration::from_secs(self.max_poll_interval),
+            give_up_after: Duration::from_secs(self.give_up_after),
+        }
+    }
+}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct OutputConfig {
+    dir: Option<PathBuf>,
+    /// 相对于 `dir` 的路径模板，见 [`crate::output::PathTemplate`].
+    template: String,
+    format: OutputFormat,
+}
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            template: "{lesson_id}.json".to_owned(),
+            format: OutputFormat::default(),
+        }
+    }
+}
+impl OutputConfig {
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+    pub fn sink(&self) -> OutputSink {
+        let template = match &self.dir {
+            Some(dir) => {
+                // 目录中的花括号不是模板变量。
+                let dir = dir.to_string_lossy().replace('{', "{{").replace('}', "}}");
+                Path::new(&dir)
+                    .join(&self.template)
+                    .to_string_lossy()
+                    .into_owned()
+            }
+            None => self.template.clone(),
+        };
+        OutputSink::new(template, self.format)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    cache_dir: Option<PathBuf>,
+    server: Server,
+    sessions: SessionsConfig,
+    scan: ScanOptions,
+    download: DownloadConfig,
+    monitor: MonitorConfig,
+    output: OutputConfig,
+    timetable: Timetable,
+}
+/// `$XDG_*` 或 `~/{fallback}` 下的 `xddcc` 目录。
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("xddcc"))
+}
+fn parse_var<T: FromStr>(name: &'static str, value: String) -> Result<T, ConfigError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ConfigError::Env { name, value })
+}
+/// 非负的 `i32`, 如扫描的年数。
+fn parse_count(name: &'static str, value: String) -> Result<i32, ConfigError> {
+    let count: u32 = parse_var(name, value.clone())?;
+    i32::try_from(count).map_err(|_| ConfigError::Env { name, value })
+}
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+impl Config {
+    /// 默认的配置文件路径，见模块文档。
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDDCC_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("config.toml")))
+    }
+    pub fn from_toml(s: &str) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+    /// 检查无法由类型保证的取值范围。
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.scan.threads < 1 {
+            return Err(ConfigError::Invalid {
+                key: "scan.threads",
+                value: self.scan.threads.to_string(),
+            });
+        }
+        if self.scan.years < 0 {
+            return Err(ConfigError::Invalid {
+                key: "scan.years",
+                value: self.scan.years.to_string(),
+            });
+        }
+        Ok(())
+    }
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(self)
+    }
+    /// 读取配置文件，不应用环境变量。
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        Config::from_toml(&std::fs::read_to_string(path)?)
+    }
+    /// 读取默认位置的配置文件（不存在时使用默认配置），并应用环境变量。
+    pub fn load_default() -> Result<Config, ConfigError> {
+        let mut config = match Config::default_path() {
+            Some(path) if path.exists() => Config::load(&path)?,
+            _ => Config::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+    /// 以环境变量覆盖配置，见模块文档。
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        self.apply_vars(|name| std::env::var(name).ok())
+    }
+    /// 同 [`Config::apply_env`], 但变量的值由 `var` 提供。
+    pub fn apply_vars(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(host) = var("XDDCC_HOST") {
+            self.server = Server::new(host, self.server.fid());
+        }
+        if let Some(fid) = var("XDDCC_FID") {
+            self.server = Server::new(self.server.host(), parse_var("XDDCC_FID", fid)?);
+        }
+        if let Some(dir) = var("XDDCC_SESSIONS_DIR") {
+            self.sessions.dir = Some(dir.into());
+        }
+        if let Some(accounts) = var("XDDCC_ACCOUNTS") {
+            self.sessions.accounts = split_list(&accounts).map(str::to_owned).collect();
+        }
+        if let Some(threads) = var("XDDCC_SCAN_THREADS") {
+            self.scan.threads = parse_count("XDDCC_SCAN_THREADS", threads)?.max(1);
+        }
+        if let Some(years) = var("XDDCC_SCAN_YEARS") {
+            self.scan.years = parse_count("XDDCC_SCAN_YEARS", years)?;
+        }
+        if let Some(rate_limit) = var("XDDCC_RATE_LIMIT") {
+            self.scan.rate_limit = Some(parse_var("XDDCC_RATE_LIMIT", rate_limit)?);
+        }
+        if let Some(concurrency) = var("XDDCC_CONCURRENCY") {
+            self.download.concurrency = parse_var("XDDCC_CONCURRENCY", concurrency)?;
+        }
+        if let Some(retries) = var("XDDCC_RETRIES") {
+            self.download.retries = parse_var("XDDCC_RETRIES", retries)?;
+        }
+        if let Some(kinds) = var("XDDCC_STREAM_KINDS") {
+            self.download.kinds = split_list(&kinds)
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| ConfigError::Env {
+                    name: "XDDCC_STREAM_KINDS",
+                    value: kinds.clone(),
+                })?;
+        }
+        if let Some(dir) = var("XDDCC_CACHE_DIR") {
+            self.cache_dir = Some(dir.into());
+        }
+        if let Some(dir) = var("XDDCC_OUTPUT_DIR") {
+            self.output.dir = Some(dir.into());
+        }
+        if let Some(format) = var("XDDCC_OUTPUT_FORMAT") {
+            self.output.format = parse_var("XDDCC_OUTPUT_FORMAT", format)?;
+        }
+        Ok(())
+    }
+    /// 由保存的会话中选中的会话组成会话池，见 [`SessionsConfig::load`].
+    pub fn session_pool(&self) -> Result<SessionPool, ConfigError> {
+        Ok(self.session_pool_with(self.sessions.load()?))
+    }
+    /// 由 `sessions` 中选中的会话组成会话池，池中的会话与其匿名客户端均使用 `server`.
+    pub fn session_pool_with(&self, sessions: impl IntoIterator<Item = Session>) -> SessionPool {
+        SessionPool::with_server(self.server.clone(), self.sessions.select(sessions))
+    }
+    /// 使用 `server` 的匿名客户端。
+    pub fn client(&self) -> AnonymousClient {
+        AnonymousClient::new().with_server(self.server.clone())
+    }
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+    pub fn sessions(&self) -> &SessionsConfig {
+        &self.sessions
+    }
+    pub fn timetable(&self) -> &Timetable {
+        &self.timetable
+    }
+    pub fn scan(&self) -> &ScanOptions {
+        &self.scan
+    }
+    pub fn download(&self) -> &DownloadConfig {
+        &self.download
+    }
+    pub fn monitor(&self) -> &MonitorConfig {
+        &self.monitor
+    }
+    pub fn output(&self) -> &OutputConfig {
+        &self.output
+    }
+    /// 缓存目录，默认为 `$XDG_CACHE_HOME/xddcc` 或 `~/.cache/xddcc`.
+    pub fn cache_dir(&self) -> Option<PathBuf> {
+        self.cache_dir
+            .clone()
+            .or_else(|| xdg_dir("XDG_CACHE_HOME", ".cache"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_config() {
+        let config = Config::from_toml(
+            r#"
+            [server]
+            fid = 1
+
+            [scan]
+            years = 2
+            rate_limit = 10
+
+            [download]
+            kinds = ["teacher_track"]
+
+            [output]
+            dir = "/srv/{x}"
+            format = "md"
+
+            [[timetable]]
+            jie = 1
+            start = "08:00"
+            end = "09:35"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server(), &Server::new("newesxidian.chaoxing.com", 1));
+        assert_eq!(config.scan().threads, 64);
+        assert_eq!(config.scan().years, 2);
+        assert_eq!(config.scan().rate_limit, Some(10));
+        assert_eq!(config.download().concurrency(), 8);
+        assert_eq!(config.output().format(), OutputFormat::Markdown);
+        assert_eq!(config.timetable().sections().len(), 1);
+        assert_eq!(
+            config.output().sink().template().as_str(),
+            "/srv/{{x}}/{lesson_id}.json"
+        );
+        assert_eq!(
+            Config::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+
+        let vars = HashMap::from([
+            ("XDDCC_HOST", "example.com"),
+            ("XDDCC_STREAM_KINDS", "ppt_video, teacher-full"),
+            ("XDDCC_ACCOUNTS", "a,b"),
+        ]);
+        let mut overridden = config.clone();
+        overridden
+            .apply_vars(|name| vars.get(name).map(|v| v.to_string()))
+            .unwrap();
+        assert_eq!(overridden.server(), &Server::new("example.com", 1));
+        assert_eq!(
+            overridden.download().kinds(),
+            [StreamKind::PptVideo, StreamKind::TeacherFull]
+        );
+        assert_eq!(overridden.sessions().accounts(), ["a", "b"]);
+        let mut invalid = config;
+        assert!(invalid
+            .apply_vars(|name| (name == "XDDCC_FID").then(|| "x".to_owned()))
+            .is_err());
+        assert!(invalid
+            .apply_vars(|name| (name == "XDDCC_SCAN_YEARS").then(|| "-1".to_owned()))
+            .is_err());
+        assert!(Config::from_toml("[scan]\nyears = -1").is_err());
+    }
+    #[test]
+    fn test_load_sessions() {
+        let dir = std::env::temp_dir().join(format!("xddcc-sessions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cookies = r#"[{
+            "raw_cookie": "JSESSIONID=abc; Path=/; Expires=Tue, 03 Aug 2100 00:38:37 GMT",
+            "path": ["/", true],
+            "domain": {"HostOnly": "newesxidian.chaoxing.com"},
+            "expires": {"AtUtc": "2100-08-03T00:38:37Z"}
+        }]"#;
+        std::fs::write(
+            dir.join("b.json"),
+            format!(r#"{{"uid": "2", "name": "乙", "cookies": {cookies}}}"#),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("a.json"),
+            r#"{"uid": "1", "name": "甲", "cookies": []}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let mut config = Config::default();
+        config
+            .apply_vars(|name| (name == "XDDCC_SESSIONS_DIR").then(|| dir.to_string_lossy().into()))
+            .unwrap();
+        let sessions = config.sessions().load().unwrap();
+        assert_eq!(
+            sessions.iter().map(|s| s.uid()).collect::<Vec<_>>(),
+            ["1", "2"]
+        );
+        let jar = sessions[1].cookie_jar_lock();
+        let cookie = jar
+            .get("newesxidian.chaoxing.com", "/", "JSESSIONID")
+            .unwrap();
+        assert_eq!(cookie.value(), "abc");
+        drop(jar);
+        config.sessions.accounts = vec!["乙".to_owned()];
+        let pool = config.session_pool().unwrap();
+        assert_eq!(pool.sessions().len(), 1);
+        assert_eq!(pool.sessions()[0].uid(), "2");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(config.sessions().load().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 录播视频的下载。
+//!
+//! 分片保存在目标目录中，下载进度记录在同目录的 [`STATE_FILE_NAME`] 文件内，
+//! 中断后以相同参数再次调用即可继续下载。
+
+use crate::{
+    hls::{HlsError, MediaPlaylist},
+    tools::{arc_into_inner_error_handler, mutex_into_inner_error_handler},
+    Client, ProgressItem, ProgressState, ProgressTracker, ProgressTrackerHolder, StreamKind,
+    VideoPath,
+};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// 记录下载进度的文件名。
+pub const STATE_FILE_NAME: &str = ".xddcc-download.json";
+
+#[derive(Debug)]
+pub enum DownloadError {
+    Hls(HlsError),
+    Io(std::io::Error),
+    /// `VideoPath` 中没有该路视频。
+    StreamNotFound(StreamKind),
+    /// 重试后仍有分片下载失败，或下载被中止。其值为未完成的分片序号。
+    Incomplete(Vec<usize>),
+}
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Hls(e) => write!(f, "{e}"),
+            DownloadError::Io(e) => write!(f, "文件读写出错：{e}"),
+            DownloadError::StreamNotFound(kind) => write!(f, "没有 `{kind}` 视频。"),
+            DownloadError::Incomplete(failed) => {
+                write!(f, "有 {} 个分片未能下载。", failed.len())
+            }
+        }
+    }
+}
+impl std::error::Error for DownloadError {}
+impl From<HlsError> for DownloadError {
+    fn from(e: HlsError) -> Self {
+        DownloadError::Hls(e)
+    }
+}
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// 同时下载的分片数。
+    pub concurrency: usize,
+    /// 单个分片失败后的重试次数。
+    pub retries: u32,
+}
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            retries: 3,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SegmentState {
+    uri: String,
+    file: String,
+    duration: f64,
+    discontinuity: bool,
+    done: bool,
+}
+impl SegmentState {
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+    /// 分片文件名，相对于下载目录。
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+    pub fn discontinuity(&self) -> bool {
+        self.discontinuity
+    }
+    pub fn done(&self) -> bool {
+        self.done
+    }
+}
+/// 下载目录中的进度记录。
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DownloadState {
+    playlist_url: String,
+    kind: StreamKind,
+    segments: Vec<SegmentState>,
+}
+impl DownloadState {
+    pub fn playlist_url(&self) -> &str {
+        &self.playlist_url
+    }
+    pub fn kind(&self) -> StreamKind {
+        self.kind
+    }
+    pub fn segments(&self) -> &[SegmentState] {
+        &self.segments
+    }
+    pub fn is_complete(&self) -> bool {
+        self.segments.iter().all(|s| s.done)
+    }
+    /// 读取 `dir` 中的进度记录，不存在或无法解析时返回 `None`.
+    pub fn load(dir: &Path) -> Option<DownloadState> {
+        let content = std::fs::read(dir.join(STATE_FILE_NAME)).ok()?;
+        match serde_json::from_slice(&content) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                warn!("下载进度记录解析出错，将重新下载：{e}.");
+                None
+            }
+        }
+    }
+    pub(crate) fn new(playlist_url: &str, kind: StreamKind) -> Self {
+        Self {
+            playlist_url: playlist_url.to_owned(),
+            kind,
+            segments: Vec::new(),
+        }
+    }
+    /// 追加一个已下载完成的分片。
+    pub(crate) fn push_done(
+        &mut self,
+        uri: &str,
+        file: String,
+        duration: f64,
+        discontinuity: bool,
+    ) {
+        self.segments.push(SegmentState {
+            uri: uri.to_owned(),
+            file,
+            duration,
+            discontinuity,
+            done: true,
+        });
+    }
+    pub(crate) fn save(&self, dir: &Path) -> std::io::Result<()> {
+        crate::output::write_atomic(
+            &dir.join(STATE_FILE_NAME),
+            &serde_json::to_vec_pretty(self)?,
+        )
+    }
+    /// 已下载完成的分片文件路径，按播放顺序排列。
+    pub fn files(&self, dir: &Path) -> Vec<PathBuf> {
+        self.segments
+            .iter()
+            .filter(|s| s.done)
+            .map(|s| dir.join(&s.file))
+            .collect()
+    }
+}
+
+fn fetch_segment(client: &impl Client, uri: &str, path: &Path) -> Result<(), DownloadError> {
+    let part = path.with_extension("part");
+    let mut reader = client
+        .agent()
+        .get(uri)
+        .call()
+        .map_err(HlsError::from)?
+        .into_body()
+        .into_reader();
+    let mut file = std::fs::File::create(&part)?;
+    std::io::copy(&mut reader, &mut file)?;
+    file.sync_all()?;
+    std::fs::rename(part, path)?;
+    Ok(())
+}
+/// 下载分片，失败后最多重试 `retries` 次，每次重试前等待的时间递增。
+pub(crate) fn fetch_segment_with_retries(
+    client: &impl Client,
+    uri: &str,
+    path: &Path,
+    retries: u32,
+) -> Result<(), DownloadError> {
+    let mut attempt = 0;
+    loop {
+        match fetch_segment(client, uri, path) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                debug!("分片 `{uri}` 下载失败，第 {attempt} 次重试：{e}.");
+                std::thread::sleep(Duration::from_secs(attempt as u64));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 根据 `dir` 中旧的进度记录生成新的进度，分片文件以媒体序列号命名。
+///
+/// 分片先写入临时文件再重命名，因此旧记录中地址与文件名均相同且文件存在的分片即为已完成，
+/// 即使进度记录尚未写入。
+fn resume_state(
+    dir: &Path,
+    playlist_url: &str,
+    kind: StreamKind,
+    playlist: &MediaPlaylist,
+) -> DownloadState {
+    let previous = DownloadState::load(dir)
+        .filter(|s| s.playlist_url == playlist_url)
+        .map(|s| {
+            s.segments
+                .into_iter()
+                .filter(|s| dir.join(&s.file).is_file())
+                .map(|s| (s.uri, s.file))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+    let segments = playlist
+        .segments()
+        .iter()
+        .map(|segment| {
+            let file = format!("{:06}.ts", segment.sequence());
+            SegmentState {
+                uri: segment.uri().to_owned(),
+                done: previous.get(segment.uri()) == Some(&file),
+                file,
+                duration: segment.duration(),
+                discontinuity: segment.discontinuity(),
+            }
+        })
+        .collect::<Vec<_>>();
+    DownloadState {
+        playlist_url: playlist_url.to_owned(),
+        kind,
+        segments,
+    }
+}
+/// 分批写入进度记录，避免每个分片完成后都重写整个文件。
+///
+/// 写入失败时只记录警告，之后的写入会再次尝试。
+pub(crate) struct StateWriter {
+    state: DownloadState,
+    unsaved: usize,
+    saved_at: Instant,
+}
+impl StateWriter {
+    /// 至少每完成这么多个分片写入一次。
+    const BATCH: usize = 32;
+    /// 至少每隔这么长时间写入一次。
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    pub(crate) fn new(state: DownloadState) -> Self {
+        Self {
+            state,
+            unsaved: 0,
+            saved_at: Instant::now(),
+        }
+    }
+    fn mark_done(&mut self, index: usize, dir: &Path) {
+        self.state.segments[index].done = true;
+        self.changed(dir);
+    }
+    /// 见 [`DownloadState::push_done`].
+    pub(crate) fn push_done(
+        &mut self,
+        uri: &str,
+        file: String,
+        duration: f64,
+        discontinuity: bool,
+        dir: &Path,
+    ) {
+        self.state.push_done(uri, file, duration, discontinuity);
+        self.changed(dir);
+    }
+    fn changed(&mut self, dir: &Path) {
+        self.unsaved += 1;
+        if self.unsaved >= Self::BATCH || self.saved_at.elapsed() >= Self::INTERVAL {
+            self.try_flush(dir);
+        }
+    }
+    pub(crate) fn flush(&mut self, dir: &Path) -> std::io::Result<()> {
+        self.state.save(dir)?;
+        self.unsaved = 0;
+        self.saved_at = Instant::now();
+        Ok(())
+    }
+    /// 同 [`StateWriter::flush`], 但失败时只记录警告。
+    pub(crate) fn try_flush(&mut self, dir: &Path) {
+        if let Err(e) = self.flush(dir) {
+            warn!("下载进度保存失败：{e}.");
+        }
+    }
+}
+/// 下载 `video_path` 中 `kind` 对应的视频的所有分片到 `dir`.
+///
+/// 成功时返回按播放顺序排列的分片文件路径。
+pub fn download_stream<C, P>(
+    client: &C,
+    video_path: &VideoPath,
+    kind: StreamKind,
+    dir: &Path,
+    options: &DownloadOptions,
+    multi: &impl ProgressTrackerHolder<P>,
+) -> Result<Vec<PathBuf>, DownloadError>
+where
+    C: Client + Clone + Send + 'static,
+    P: ProgressTracker + 'static,
+{
+    let playlist_url = video_path
+        .get(kind)
+        .ok_or(DownloadError::StreamNotFound(kind))?;
+    let playlist = video_path
+        .media_playlist(client, kind)?
+        .ok_or(DownloadError::StreamNotFound(kind))?;
+    if playlist.is_encrypted() {
+        warn!("`{kind}` 视频已加密，分片将按原样保存。");
+    }
+    std::fs::create_dir_all(dir)?;
+    let state = resume_state(dir, playlist_url, kind, &playlist);
+    state.save(dir)?;
+    let queue = state
+        .segments
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !s.done)
+        .map(|(index, s)| (index, s.uri.clone(), dir.join(&s.file)))
+        .collect::<VecDeque<_>>();
+    debug!(
+        "download_stream: {} 个分片，其中 {} 个待下载。",
+        state.segments.len(),
+        queue.len()
+    );
+    let pb = multi.init(queue.len() as u64, ProgressState::DownloadSegments { kind });
+    let pb = Arc::new(Mutex::new(pb));
+    let queue = Arc::new(Mutex::new(queue));
+    let state = Arc::new(Mutex::new(StateWriter::new(state)));
+    let mut handles = Vec::new();
+    for _ in 0..options.concurrency.max(1) {
+        let client = client.clone();
+        let queue = Arc::clone(&queue);
+        let state = Arc::clone(&state);
+        let pb = Arc::clone(&pb);
+        let dir = dir.to_owned();
+        let retries = options.retries;
+        let handle = std::thread::spawn(move || loop {
+            if !pb.lock().unwrap().go_on() {
+                debug!("download_stream: break.");
+                break;
+            }
+            let Some((index, uri, path)) = queue.lock().unwrap().pop_front() else {
+                break;
+            };
+            let item = ProgressItem::Segment { kind, index };
+            let ok = match fetch_segment_with_retries(&client, &uri, &path, retries) {
+                Ok(()) => {
+                    pb.lock().unwrap().on_item(Ok(()), &item);
+                    true
+                }
+                Err(e) => {
+                    warn!("分片 {index} 下载失败：{e}.");
+                    pb.lock().unwrap().on_item(Err(&e), &item);
+                    false
+                }
+            };
+            if ok {
+                state.lock().unwrap().mark_done(index, &dir);
+            }
+            pb.lock().unwrap().inc(1);
+        });
+        handles.push(handle);
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let pb = Arc::into_inner(pb)
+        .unwrap_or_else(arc_into_inner_error_handler)
+        .into_inner()
+        .unwrap_or_else(mutex_into_inner_error_handler);
+    pb.finish(ProgressState::DownloadSegments { kind });
+    multi.remove_progress(&pb);
+    let mut writer = Arc::into_inner(state)
+        .unwrap_or_else(arc_into_inner_error_handler)
+        .into_inner()
+        .unwrap_or_else(mutex_into_inner_error_handler);
+    writer.flush(dir)?;
+    let state = writer.state;
+    let failed = state
+        .segments
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !s.done)
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        Ok(state.files(dir))
+    } else {
+        Err(DownloadError::Incomplete(failed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hls::Playlist;
+
+    #[test]
+    fn test_resume_state() {
+        let dir = std::env::temp_dir().join(format!("xddcc-download-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = "http://a.b/r/index.m3u8";
+        let text = "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:7\n\
+            #EXTINF:10,\na.ts\n#EXTINF:10,\nb.ts\n#EXTINF:10,\nc.ts\n#EXT-X-ENDLIST\n";
+        let Playlist::Media(playlist) = Playlist::parse(url, text).unwrap() else {
+            panic!()
+        };
+        // `b.ts` 已下载完成，但进度记录尚未写入；`c.ts` 的文件已被删除。
+        let mut previous = DownloadState::new(url, StreamKind::PptVideo);
+        previous.push_done("http://a.b/r/a.ts", "000007.ts".to_owned(), 10.0, false);
+        previous.segments.push(SegmentState {
+            uri: "http://a.b/r/b.ts".to_owned(),
+            file: "000008.ts".to_owned(),
+            duration: 10.0,
+            discontinuity: false,
+            done: false,
+        });
+        previous.push_done("http://a.b/r/c.ts", "000009.ts".to_owned(), 10.0, false);
+        previous.save(&dir).unwrap();
+        std::fs::write(dir.join("000007.ts"), b"a").unwrap();
+        std::fs::write(dir.join("000008.ts"), b"b").unwrap();
+        let state = resume_state(&dir, url, StreamKind::PptVideo, &playlist);
+        let done = state
+            .segments()
+            .iter()
+            .map(|s| s.done())
+            .collect::<Vec<_>>();
+        assert_eq!(done, [true, true, false]);
+        // 播放列表地址不同时重新下载。
+        let state = resume_state(&dir, "http://a.b/x.m3u8", StreamKind::PptVideo, &playlist);
+        assert!(state.segments().iter().all(|s| !s.done()));
+        // 未满一批时不写入，结束时写入。
+        let mut writer = StateWriter::new(state);
+        writer.mark_done(2, &dir);
+        assert_eq!(DownloadState::load(&dir).unwrap().playlist_url(), url);
+        writer.flush(&dir).unwrap();
+        let saved = DownloadState::load(&dir).unwrap();
+        assert_eq!(saved.playlist_url(), "http://a.b/x.m3u8");
+        assert!(saved.segments()[2].done());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 事件与事件处理。
+//!
+//! 新录播、新教室、直播开始等变化以 [`Event`] 表示，由 [`EventBus`] 分发给各个 [`EventSink`]:
+//! 执行本地命令（[`CommandSink`]）、发送 HTTP POST 请求（[`WebhookSink`]）或追加到
+//! NDJSON 文件（[`NdjsonSink`]）。各处理方收到的内容均为 [`EventRecord`] 的 JSON 形式。
+
+use crate::{monitor::MonitorEvent, Room, VideoPath};
+use chrono::Local;
+use log::warn;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    io::Write,
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+};
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// 课次的录播已发布。
+    NewRecording {
+        lesson_id: i64,
+        start_time: i64,
+        video_path: VideoPath,
+    },
+    /// 发现了新的教室。
+    NewRoom { name: String, device_code: String },
+    /// 直播开始。
+    LiveStarted {
+        uid: String,
+        name: String,
+        room: Room,
+        video_path: VideoPath,
+    },
+}
+impl Event {
+    /// 比较两次 [`Room::get_all_rooms`] 的结果，为新出现的教室生成事件。
+    pub fn new_rooms(old: &HashMap<String, String>, new: &HashMap<String, String>) -> Vec<Event> {
+        let mut rooms = new
+            .iter()
+            .filter(|(name, _)| !old.contains_key(*name))
+            .collect::<Vec<_>>();
+        rooms.sort();
+        rooms
+            .into_iter()
+            .map(|(name, device_code)| Event::NewRoom {
+                name: name.clone(),
+                device_code: device_code.clone(),
+            })
+            .collect()
+    }
+    /// 为 [`crate::Live::get_lives_now`] 的结果生成事件。
+    pub fn lives_started<'a>(
+        lives: impl IntoIterator<Item = (&'a str, (&'a str, Room, VideoPath))>,
+    ) -> Vec<Event> {
+        lives
+            .into_iter()
+            .map(|(uid, (name, room, video_path))| Event::LiveStarted {
+                uid: uid.to_owned(),
+                name: name.to_owned(),
+                room,
+                video_path,
+            })
+            .collect()
+    }
+}
+/// 监视器放弃等待的课次不产生事件。
+impl TryFrom<MonitorEvent> for Event {
+    type Error = MonitorEvent;
+
+    fn try_from(event: MonitorEvent) -> Result<Self, Self::Error> {
+        match event {
+            MonitorEvent::Available {
+                lesson_id,
+                start_time,
+                video_path,
+            } => Ok(Event::NewRecording {
+                lesson_id,
+                start_time,
+                video_path,
+            }),
+            e => Err(e),
+        }
+    }
+}
+/// 带有产生时间的事件，即各处理方实际收到的内容。
+#[derive(Serialize, Debug, Clone)]
+pub struct EventRecord {
+    /// 事件产生的时间，毫秒时间戳。
+    pub time: i64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+impl EventRecord {
+    pub fn now(event: Event) -> Self {
+        Self {
+            time: Local::now().timestamp_millis(),
+            event,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EventError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Http(Box<ureq::Error>),
+    /// 命令以非零状态退出。
+    Command(ExitStatus),
+}
+impl Display for EventError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventError::Io(e) => write!(f, "读写出错：{e}"),
+            EventError::Json(e) => write!(f, "事件序列化出错：{e}"),
+            EventError::Http(e) => write!(f, "请求出错：{e}"),
+            EventError::Command(status) => write!(f, "命令执行失败：{status}"),
+        }
+    }
+}
+impl std::error::Error for EventError {}
+impl From<std::io::Error> for EventError {
+    fn from(e: std::io::Error) -> Self {
+        EventError::Io(e)
+    }
+}
+impl From<serde_json::Error> for EventError {
+    fn from(e: serde_json::Error) -> Self {
+        EventError::Json(e)
+    }
+}
+impl From<ureq::Error> for EventError {
+    fn from(e: ureq::Error) -> Self {
+        EventError::Http(Box::new(e))
+    }
+}
+
+pub trait EventSink: Send + Sync {
+    fn send(&self, record: &EventRecord) -> Result<(), EventError>;
+}
+/// 执行本地命令，事件的 JSON 经由标准输入传入。
+pub struct CommandSink {
+    program: String,
+    args: Vec<String>,
+}
+impl CommandSink {
+    pub fn new(program: impl Into<String>, args: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().collect(),
+        }
+    }
+}
+impl EventSink for CommandSink {
+    fn send(&self, record: &EventRecord) -> Result<(), EventError> {
+        let json = serde_json::to_vec(record)?;
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&json)?;
+        }
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(EventError::Command(status))
+        }
+    }
+}
+/// 以 JSON 为请求体向 `url` 发送 POST 请求。
+pub struct WebhookSink {
+    agent: ureq::Agent,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+impl WebhookSink {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            agent: ureq::Agent::new_with_defaults(),
+            url: url.into(),
+            headers: Vec::new(),
+        }
+    }
+    /// 附加请求头，如 `Authorization`.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+impl EventSink for WebhookSink {
+    fn send(&self, record: &EventRecord) -> Result<(), EventError> {
+        let mut request = self.agent.post(&self.url);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request.send_json(record)?;
+        Ok(())
+    }
+}
+/// 将事件逐行追加到 NDJSON 文件。
+pub struct NdjsonSink {
+    path: PathBuf,
+}
+impl NdjsonSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+impl EventSink for NdjsonSink {
+    fn send(&self, record: &EventRecord) -> Result<(), EventError> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // 整行一次写入，避免多个进程同时追加时内容交错。
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+/// 将事件分发给所有处理方。
+#[derive(Default)]
+pub struct EventBus {
+    sinks: Vec<Box<dyn EventSink>>,
+}
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_sink(&mut self, sink: impl EventSink + 'static) -> &mut Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+    /// 分发事件。某个处理方失败不影响其他处理方，失败的错误会被返回。
+    pub fn emit(&self, event: Event) -> Vec<EventError> {
+        let record = EventRecord::now(event);
+        let mut errors = Vec::new();
+        for sink in &self.sinks {
+            if let Err(e) = sink.send(&record) {
+                warn!("事件处理失败：{e}.");
+                errors.push(e);
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+
+    fn test_event() -> Event {
+        Event::NewRoom {
+            name: "A-101".to_owned(),
+            device_code: "abc".to_owned(),
+        }
+    }
+    #[test]
+    fn test_webhook_sink() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            (request_line, String::from_utf8(body).unwrap())
+        });
+        let mut bus = EventBus::new();
+        bus.add_sink(WebhookSink::new(url));
+        assert!(bus.emit(test_event()).is_empty());
+        let (request_line, body) = server.join().unwrap();
+        assert!(request_line.starts_with("POST /hook "));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["type"], "new_room");
+        assert_eq!(body["name"], "A-101");
+        assert!(body["time"].is_i64());
+    }
+    #[test]
+    fn test_ndjson_sink() {
+        let path = std::env::temp_dir().join(format!("xddcc-event-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut bus = EventBus::new();
+        bus.add_sink(NdjsonSink::new(&path));
+        bus.emit(test_event());
+        bus.emit(test_event());
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        for line in content.lines() {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["device_code"], "abc");
+        }
+    }
+}
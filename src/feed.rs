@@ -0,0 +1,270 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 课程录播的 RSS 2.0 / Atom 订阅源。
+//!
+//! [`Feed`] 本身可以序列化保存，新录播出现后读取、调用 [`Feed::add_recordings`] 追加并重新生成即可。
+
+use crate::{StreamKind, VideoPath};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct FeedItem {
+    start_time: i64,
+    title: String,
+    enclosure_url: String,
+    enclosure_type: String,
+    length: Option<u64>,
+}
+impl FeedItem {
+    pub fn start_time(&self) -> i64 {
+        self.start_time
+    }
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+    pub fn enclosure_url(&self) -> &str {
+        &self.enclosure_url
+    }
+}
+/// 一门课程的订阅源，各项以课次开始时间为键。
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Feed {
+    live_id: i64,
+    title: String,
+    link: String,
+    items: BTreeMap<i64, FeedItem>,
+}
+fn local_time(millis: i64) -> DateTime<Local> {
+    DateTime::from_timestamp_millis(millis)
+        .unwrap_or_default()
+        .with_timezone(&Local)
+}
+pub(crate) fn lesson_title(course: &str, start_time: i64) -> String {
+    format!(
+        "{course} {}",
+        local_time(start_time).format("%Y-%m-%d %H:%M")
+    )
+}
+pub(crate) fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+impl Feed {
+    /// `link` 为订阅源指向的网页地址，RSS 2.0 要求必须提供。
+    pub fn new(live_id: i64, title: impl Into<String>, link: impl Into<String>) -> Self {
+        Self {
+            live_id,
+            title: title.into(),
+            link: link.into(),
+            items: BTreeMap::new(),
+        }
+    }
+    pub fn link(&self) -> &str {
+        &self.link
+    }
+    pub fn set_link(&mut self, link: impl Into<String>) {
+        self.link = link.into();
+    }
+    pub fn items(&self) -> impl Iterator<Item = &FeedItem> {
+        self.items.values()
+    }
+    /// 追加 [`crate::lesson::Lesson::get_recording_lives`] 的结果中尚未收录、且有 `kind` 视频的课次。
+    ///
+    /// 返回新增的项数。
+    pub fn add_recordings<'a>(
+        &mut self,
+        recordings: impl IntoIterator<Item = (&'a i64, &'a VideoPath)>,
+        kind: StreamKind,
+    ) -> usize {
+        let mut count = 0;
+        for (start_time, video_path) in recordings {
+            if self.items.contains_key(start_time) {
+                continue;
+            }
+            let Some(url) = video_path.get(kind) else {
+                continue;
+            };
+            self.items.insert(
+                *start_time,
+                FeedItem {
+                    start_time: *start_time,
+                    title: lesson_title(&self.title, *start_time),
+                    enclosure_url: url.to_owned(),
+                    enclosure_type: "application/vnd.apple.mpegurl".to_owned(),
+                    length: None,
+                },
+            );
+            count += 1;
+        }
+        count
+    }
+    /// 将课次的附件替换为已下载的本地文件，`url` 为该文件可供访问的地址。
+    pub fn set_local_file(&mut self, start_time: i64, path: &Path, url: impl Into<String>) {
+        let length = std::fs::metadata(path).ok().map(|m| m.len());
+        let item = self.items.entry(start_time).or_insert_with(|| FeedItem {
+            start_time,
+            title: lesson_title(&self.title, start_time),
+            enclosure_url: String::new(),
+            enclosure_type: String::new(),
+            length: None,
+        });
+        item.enclosure_url = url.into();
+        item.enclosure_type = "video/mp2t".to_owned();
+        item.length = length;
+    }
+    fn guid(&self, start_time: i64) -> String {
+        format!("urn:xddcc:{}:{start_time}", self.live_id)
+    }
+    pub fn render(&self, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Rss => self.to_rss(),
+            FeedFormat::Atom => self.to_atom(),
+        }
+    }
+    pub fn to_rss(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n<channel>\n",
+        );
+        let _ = writeln!(xml, "<title>{}</title>", escape_xml(&self.title));
+        let _ = writeln!(xml, "<link>{}</link>", escape_xml(&self.link));
+        let _ = writeln!(
+            xml,
+            "<description>{}</description>",
+            escape_xml(&self.title)
+        );
+        if let Some(last) = self.items.keys().next_back() {
+            let _ = writeln!(
+                xml,
+                "<lastBuildDate>{}</lastBuildDate>",
+                local_time(*last).to_rfc2822()
+            );
+        }
+        for item in self.items.values() {
+            xml.push_str("<item>\n");
+            let _ = writeln!(xml, "<title>{}</title>", escape_xml(&item.title));
+            let _ = writeln!(
+                xml,
+                "<guid isPermaLink=\"false\">{}</guid>",
+                self.guid(item.start_time)
+            );
+            let _ = writeln!(
+                xml,
+                "<pubDate>{}</pubDate>",
+                local_time(item.start_time).to_rfc2822()
+            );
+            let _ = writeln!(
+                xml,
+                "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>",
+                escape_xml(&item.enclosure_url),
+                item.length.unwrap_or(0),
+                escape_xml(&item.enclosure_type)
+            );
+            xml.push_str("</item>\n");
+        }
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+    pub fn to_atom(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+        );
+        let _ = writeln!(xml, "<id>urn:xddcc:{}</id>", self.live_id);
+        let _ = writeln!(xml, "<title>{}</title>", escape_xml(&self.title));
+        let _ = writeln!(xml, "<link href=\"{}\"/>", escape_xml(&self.link));
+        let updated = self.items.keys().next_back().copied().unwrap_or_default();
+        let _ = writeln!(
+            xml,
+            "<updated>{}</updated>",
+            local_time(updated).to_rfc3339()
+        );
+        xml.push_str("<author><name>xddcc</name></author>\n");
+        for item in self.items.values() {
+            xml.push_str("<entry>\n");
+            let _ = writeln!(xml, "<id>{}</id>", self.guid(item.start_time));
+            let _ = writeln!(xml, "<title>{}</title>", escape_xml(&item.title));
+            let _ = writeln!(
+                xml,
+                "<updated>{}</updated>",
+                local_time(item.start_time).to_rfc3339()
+            );
+            let length = item
+                .length
+                .map(|l| format!(" length=\"{l}\""))
+                .unwrap_or_default();
+            let _ = writeln!(
+                xml,
+                "<link rel=\"enclosure\" href=\"{}\" type=\"{}\"{length}/>",
+                escape_xml(&item.enclosure_url),
+                escape_xml(&item.enclosure_type)
+            );
+            xml.push_str("</entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+    pub fn load(path: &Path) -> Option<Feed> {
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+    }
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        crate::output::write_atomic(path, &serde_json::to_vec_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_feed_incremental() {
+        let video_path: VideoPath =
+            serde_json::from_str(r#"{"ppt_video":"http://a/b.m3u8?x=1&y=2","teacher_full":null,"teacher_track":null,"student_full":null}"#)
+                .unwrap();
+        let mut recordings = HashMap::new();
+        recordings.insert(1_700_000_000_000, video_path.clone());
+        recordings.insert(1_600_000_000_000, VideoPath::default());
+        let mut feed = Feed::new(42, "A & B", "http://a/");
+        assert_eq!(feed.add_recordings(&recordings, StreamKind::PptVideo), 1);
+        assert_eq!(feed.add_recordings(&recordings, StreamKind::PptVideo), 0);
+        let rss = feed.to_rss();
+        assert!(rss.contains("<title>A &amp; B</title>"));
+        assert!(rss.contains("url=\"http://a/b.m3u8?x=1&amp;y=2\""));
+        assert!(rss.contains("urn:xddcc:42:1700000000000"));
+        assert!(rss.contains("<link>http://a/</link>"));
+        let atom = feed.to_atom();
+        assert_eq!(atom.matches("<entry>").count(), 1);
+        assert!(atom.contains("<link href=\"http://a/\"/>"));
+        feed.set_link("http://a/?c=1&d=2");
+        assert!(feed.to_rss().contains("<link>http://a/?c=1&amp;d=2</link>"));
+    }
+}
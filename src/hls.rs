@@ -0,0 +1,475 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! HLS (m3u8) 播放列表的获取与解析。
+
+use crate::Client;
+use log::debug;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum HlsError {
+    Request(Box<ureq::Error>),
+    /// 播放列表格式有误，`line` 从 1 开始计数。
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// 播放列表本身有效，但无法得到媒体播放列表，如主播放列表中没有码率。
+    NoMedia(String),
+}
+impl Display for HlsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HlsError::Request(e) => write!(f, "播放列表请求失败：{e}"),
+            HlsError::Parse { line, message } => {
+                write!(f, "播放列表解析失败（第 {line} 行）：{message}")
+            }
+            HlsError::NoMedia(message) => write!(f, "无法获取媒体播放列表：{message}"),
+        }
+    }
+}
+impl std::error::Error for HlsError {}
+impl From<Box<ureq::Error>> for HlsError {
+    fn from(e: Box<ureq::Error>) -> Self {
+        HlsError::Request(e)
+    }
+}
+impl From<ureq::Error> for HlsError {
+    fn from(e: ureq::Error) -> Self {
+        HlsError::Request(Box::new(e))
+    }
+}
+fn parse_error<T>(line: usize, message: impl Into<String>) -> Result<T, HlsError> {
+    Err(HlsError::Parse {
+        line,
+        message: message.into(),
+    })
+}
+
+/// 加密信息，对应 `EXT-X-KEY` 标签。
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Key {
+    method: String,
+    uri: Option<String>,
+    iv: Option<String>,
+}
+impl Key {
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+    pub fn uri(&self) -> Option<&str> {
+        self.uri.as_deref()
+    }
+    pub fn iv(&self) -> Option<&str> {
+        self.iv.as_deref()
+    }
+    /// `METHOD=NONE` 表示其后的分片不再加密。
+    pub fn is_none(&self) -> bool {
+        self.method == "NONE"
+    }
+}
+/// 主播放列表中的一个码率。
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Variant {
+    uri: String,
+    bandwidth: u64,
+    resolution: Option<(u32, u32)>,
+    codecs: Option<String>,
+}
+impl Variant {
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+    pub fn bandwidth(&self) -> u64 {
+        self.bandwidth
+    }
+    pub fn resolution(&self) -> Option<(u32, u32)> {
+        self.resolution
+    }
+    pub fn codecs(&self) -> Option<&str> {
+        self.codecs.as_deref()
+    }
+}
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MasterPlaylist {
+    variants: Vec<Variant>,
+}
+impl MasterPlaylist {
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+    /// 码率最高的一项。
+    pub fn best_variant(&self) -> Option<&Variant> {
+        self.variants.iter().max_by_key(|v| v.bandwidth)
+    }
+}
+/// 媒体播放列表中的一个分片。
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Segment {
+    uri: String,
+    duration: f64,
+    sequence: u64,
+    discontinuity: bool,
+    key: Option<Key>,
+}
+impl Segment {
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+    /// 时长，单位为秒。
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+    /// 媒体序列号，即 `EXT-X-MEDIA-SEQUENCE` 加上分片在列表中的位置。
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+    /// 该分片之前是否有 `EXT-X-DISCONTINUITY` 标签。
+    pub fn discontinuity(&self) -> bool {
+        self.discontinuity
+    }
+    /// 该分片生效的加密信息，未加密时为 `None`.
+    pub fn key(&self) -> Option<&Key> {
+        self.key.as_ref()
+    }
+}
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MediaPlaylist {
+    target_duration: u64,
+    media_sequence: u64,
+    discontinuity_sequence: u64,
+    playlist_type: Option<String>,
+    end_list: bool,
+    segments: Vec<Segment>,
+}
+impl MediaPlaylist {
+    pub fn target_duration(&self) -> u64 {
+        self.target_duration
+    }
+    pub fn media_sequence(&self) -> u64 {
+        self.media_sequence
+    }
+    pub fn discontinuity_sequence(&self) -> u64 {
+        self.discontinuity_sequence
+    }
+    /// `EXT-X-PLAYLIST-TYPE` 的值，即 `VOD` 或 `EVENT`.
+    pub fn playlist_type(&self) -> Option<&str> {
+        self.playlist_type.as_deref()
+    }
+    /// 是否有 `EXT-X-ENDLIST` 标签。
+    pub fn end_list(&self) -> bool {
+        self.end_list
+    }
+    /// 已结束的录播（不会再有新的分片）返回 `true`, 直播返回 `false`.
+    pub fn is_finished(&self) -> bool {
+        self.end_list || self.playlist_type.as_deref() == Some("VOD")
+    }
+    pub fn is_encrypted(&self) -> bool {
+        self.segments.iter().any(|s| s.key.is_some())
+    }
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+    /// 所有分片的总时长，单位为秒。
+    pub fn duration(&self) -> f64 {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+}
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum Playlist {
+    Master(MasterPlaylist),
+    Media(MediaPlaylist),
+}
+impl Playlist {
+    /// 获取并解析播放列表，其中的相对地址会以 `url` 为基准解析为绝对地址。
+    pub fn fetch(client: &impl Client, url: &str) -> Result<Playlist, HlsError> {
+        let text = client
+            .agent()
+            .get(url)
+            .call()?
+            .into_body()
+            .read_to_string()?;
+        Playlist::parse(url, &text)
+    }
+    /// 解析播放列表文本，`base_url` 用于解析其中的相对地址。
+    pub fn parse(base_url: &str, text: &str) -> Result<Playlist, HlsError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty());
+        match lines.next() {
+            Some((_, "#EXTM3U")) => (),
+            Some((line, _)) => return parse_error(line, "缺少 `#EXTM3U` 头。"),
+            None => return parse_error(1, "播放列表为空。"),
+        }
+        let mut is_master = false;
+        let mut variants = Vec::new();
+        let mut pending_variant: Option<Variant> = None;
+        let mut target_duration = 0;
+        let mut media_sequence = 0;
+        let mut discontinuity_sequence = 0;
+        let mut playlist_type = None;
+        let mut end_list = false;
+        let mut segments = Vec::new();
+        let mut pending_duration: Option<f64> = None;
+        let mut pending_discontinuity = false;
+        let mut key: Option<Key> = None;
+        for (line, content) in lines {
+            if let Some(tag) = content.strip_prefix('#') {
+                let (name, value) = tag.split_once(':').unwrap_or((tag, ""));
+                match name {
+                    "EXT-X-STREAM-INF" => {
+                        is_master = true;
+                        let attrs = parse_attributes(value);
+                        let bandwidth = match attr(&attrs, "BANDWIDTH").map(str::parse) {
+                            Some(Ok(b)) => b,
+                            _ => {
+                                return parse_error(
+                                    line,
+                                    "`EXT-X-STREAM-INF` 缺少有效的 `BANDWIDTH`.",
+                                )
+                            }
+                        };
+                        let resolution = attr(&attrs, "RESOLUTION").and_then(|r| {
+                            let (w, h) = r.split_once(['x', 'X'])?;
+                            Some((w.parse().ok()?, h.parse().ok()?))
+                        });
+                        let codecs = attr(&attrs, "CODECS").map(str::to_owned);
+                        pending_variant = Some(Variant {
+                            uri: String::new(),
+                            bandwidth,
+                            resolution,
+                            codecs,
+                        });
+                    }
+                    "EXT-X-TARGETDURATION" => {
+                        target_duration = match value.parse() {
+                            Ok(d) => d,
+                            Err(_) => return parse_error(line, "无效的 `EXT-X-TARGETDURATION`."),
+                        }
+                    }
+                    "EXT-X-MEDIA-SEQUENCE" => {
+                        media_sequence = match value.parse() {
+                            Ok(s) => s,
+                            Err(_) => return parse_error(line, "无效的 `EXT-X-MEDIA-SEQUENCE`."),
+                        }
+                    }
+                    "EXT-X-DISCONTINUITY-SEQUENCE" => {
+                        discontinuity_sequence = match value.parse() {
+                            Ok(s) => s,
+                            Err(_) => {
+                                return parse_error(line, "无效的 `EXT-X-DISCONTINUITY-SEQUENCE`.")
+                            }
+                        }
+                    }
+                    "EXT-X-PLAYLIST-TYPE" => playlist_type = Some(value.to_owned()),
+                    "EXT-X-ENDLIST" => end_list = true,
+                    "EXT-X-DISCONTINUITY" => pending_discontinuity = true,
+                    "EXT-X-KEY" => {
+                        let attrs = parse_attributes(value);
+                        let method = match attr(&attrs, "METHOD") {
+                            Some(m) => m.to_owned(),
+                            None => return parse_error(line, "`EXT-X-KEY` 缺少 `METHOD`."),
+                        };
+                        let k = Key {
+                            method,
+                            uri: attr(&attrs, "URI").map(|u| resolve_uri(base_url, u)),
+                            iv: attr(&attrs, "IV").map(str::to_owned),
+                        };
+                        key = if k.is_none() { None } else { Some(k) };
+                    }
+                    "EXTINF" => {
+                        let duration = value.split(',').next().unwrap_or_default().trim();
+                        pending_duration = match duration.parse() {
+                            Ok(d) => Some(d),
+                            Err(_) => return parse_error(line, "无效的 `EXTINF` 时长。"),
+                        };
+                    }
+                    _ => debug!("hls: 忽略标签 `{name}`."),
+                }
+            } else if is_master {
+                let Some(mut variant) = pending_variant.take() else {
+                    return parse_error(line, "地址前缺少 `EXT-X-STREAM-INF`.");
+                };
+                variant.uri = resolve_uri(base_url, content);
+                variants.push(variant);
+            } else {
+                let Some(duration) = pending_duration.take() else {
+                    return parse_error(line, "分片地址前缺少 `EXTINF`.");
+                };
+                segments.push(Segment {
+                    uri: resolve_uri(base_url, content),
+                    duration,
+                    sequence: media_sequence + segments.len() as u64,
+                    discontinuity: std::mem::take(&mut pending_discontinuity),
+                    key: key.clone(),
+                });
+            }
+        }
+        if is_master {
+            Ok(Playlist::Master(MasterPlaylist { variants }))
+        } else {
+            Ok(Playlist::Media(MediaPlaylist {
+                target_duration,
+                media_sequence,
+                discontinuity_sequence,
+                playlist_type,
+                end_list,
+                segments,
+            }))
+        }
+    }
+    /// 获取媒体播放列表。若 `url` 指向主播放列表，则选取码率最高的一项。
+    pub fn fetch_media(client: &impl Client, url: &str) -> Result<MediaPlaylist, HlsError> {
+        match Playlist::fetch(client, url)? {
+            Playlist::Media(media) => Ok(media),
+            Playlist::Master(master) => match master.best_variant() {
+                Some(variant) => match Playlist::fetch(client, variant.uri())? {
+                    Playlist::Media(media) => Ok(media),
+                    Playlist::Master(_) => Err(HlsError::NoMedia("主播放列表嵌套。".to_owned())),
+                },
+                None => Err(HlsError::NoMedia("主播放列表中没有可用的码率。".to_owned())),
+            },
+        }
+    }
+}
+/// 解析形如 `A=1,B="x,y"` 的属性列表。
+fn parse_attributes(value: &str) -> Vec<(&str, &str)> {
+    let mut attrs = Vec::new();
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        let Some((name, after)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+            match quoted.split_once('"') {
+                Some((v, a)) => (v, a),
+                None => (quoted, ""),
+            }
+        } else {
+            match after.split_once(',') {
+                Some((v, a)) => (v, a),
+                None => (after, ""),
+            }
+        };
+        attrs.push((name.trim(), value));
+        rest = after.trim_start_matches(',').trim_start();
+    }
+    attrs
+}
+fn attr<'a>(attrs: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    attrs.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+/// 以 `base` 为基准将 `uri` 解析为绝对地址。
+pub(crate) fn resolve_uri(base: &str, uri: &str) -> String {
+    if uri.contains("://") {
+        return uri.to_owned();
+    }
+    let base = base.split(['?', '#']).next().unwrap_or_default();
+    // `//host/path` 只继承协议。
+    if uri.starts_with("//") {
+        let scheme = base.find("://").map(|i| &base[..i]).unwrap_or("http");
+        return format!("{scheme}:{uri}");
+    }
+    let origin_end = base
+        .find("://")
+        .map(|i| {
+            base[i + 3..]
+                .find('/')
+                .map(|j| i + 3 + j)
+                .unwrap_or(base.len())
+        })
+        .unwrap_or(0);
+    if uri.starts_with('/') {
+        format!("{}{uri}", &base[..origin_end])
+    } else {
+        match base[origin_end..].rfind('/') {
+            Some(i) => format!("{}{uri}", &base[..origin_end + i + 1]),
+            None => format!("{base}/{uri}"),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_master() {
+        let text = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=1280x720,CODECS=\"avc1.4d401f,mp4a.40.2\"\n\
+            720p/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=200000\n\
+            http://other.host/low.m3u8\n";
+        let Playlist::Master(master) = Playlist::parse("http://a.b/live/x.m3u8?t=1", text).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(master.variants().len(), 2);
+        let best = master.best_variant().unwrap();
+        assert_eq!(best.uri(), "http://a.b/live/720p/index.m3u8");
+        assert_eq!(best.resolution(), Some((1280, 720)));
+        assert_eq!(best.codecs(), Some("avc1.4d401f,mp4a.40.2"));
+        assert_eq!(master.variants()[1].uri(), "http://other.host/low.m3u8");
+    }
+    #[test]
+    fn test_parse_media() {
+        let text = "#EXTM3U\n\
+            #EXT-X-TARGETDURATION:10\n\
+            #EXT-X-MEDIA-SEQUENCE:7\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"/key\",IV=0x01\n\
+            #EXTINF:10.0,\n\
+            a.ts\n\
+            #EXT-X-DISCONTINUITY\n\
+            #EXT-X-KEY:METHOD=NONE\n\
+            #EXTINF:4.5,\n\
+            b.ts\n\
+            #EXT-X-ENDLIST\n";
+        let Playlist::Media(media) = Playlist::parse("http://a.b/r/index.m3u8", text).unwrap()
+        else {
+            panic!()
+        };
+        assert!(media.is_finished());
+        assert!(media.is_encrypted());
+        assert_eq!(media.duration(), 14.5);
+        let segments = media.segments();
+        assert_eq!(segments[0].sequence(), 7);
+        assert_eq!(segments[0].key().unwrap().uri(), Some("http://a.b/key"));
+        assert!(!segments[0].discontinuity());
+        assert_eq!(segments[1].uri(), "http://a.b/r/b.ts");
+        assert_eq!(segments[1].sequence(), 8);
+        assert!(segments[1].discontinuity());
+        assert!(segments[1].key().is_none());
+    }
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Playlist::parse("http://a.b/", "not a playlist").is_err());
+        assert!(Playlist::parse("http://a.b/", "#EXTM3U\na.ts\n").is_err());
+        assert!(matches!(
+            Playlist::parse("http://a.b/", "\n"),
+            Err(HlsError::Parse { line: 1, .. })
+        ));
+    }
+    #[test]
+    fn test_resolve_uri() {
+        let base = "https://a.b/live/x.m3u8?t=1";
+        assert_eq!(resolve_uri(base, "//c.d/s/1.ts"), "https://c.d/s/1.ts");
+        assert_eq!(resolve_uri(base, "/s/1.ts"), "https://a.b/s/1.ts");
+        assert_eq!(resolve_uri(base, "1.ts?k=v"), "https://a.b/live/1.ts?k=v");
+        assert_eq!(resolve_uri(base, "http://e.f/1.ts"), "http://e.f/1.ts");
+    }
+}
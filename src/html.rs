@@ -0,0 +1,194 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 同时观看多路直播的 HTML 页面。
+//!
+//! 生成的页面为单个文件，样式与页面自身的脚本均内联。支持 HLS 的浏览器（Safari 等）直接播放，
+//! 其余浏览器通过 hls.js 播放。hls.js 不随页面内联，默认从 jsDelivr 加载，
+//! 离线使用时需以 [`MultiView::player_script`] 改为本地文件的地址。
+//! 学校的直播地址为 HTTP, 以 HTTPS 提供页面时可改用 HLS 中继（`server` 特性下的 `xddcc::relay`）的地址。
+
+use crate::{feed::escape_xml, Client, Room, StreamKind, VideoPath};
+use std::fmt::Write;
+
+/// 默认的 hls.js 地址，位于 jsDelivr, 打开页面时需要联网。
+pub const HLS_JS_URL: &str = "https://cdn.jsdelivr.net/npm/hls.js@1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tile {
+    title: String,
+    url: String,
+}
+impl Tile {
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+/// 多路直播页面，各路视频按网格排列。
+#[derive(Debug, Clone)]
+pub struct MultiView {
+    title: String,
+    columns: Option<usize>,
+    player_script: String,
+    tiles: Vec<Tile>,
+}
+const STYLE: &str = "body{margin:0;background:#111;color:#eee;font-family:sans-serif}\
+h1{font-size:1rem;margin:.5rem}\
+main{display:grid;gap:4px;padding:4px}\
+figure{margin:0;position:relative;background:#000}\
+figcaption{position:absolute;top:0;left:0;padding:2px 6px;background:rgba(0,0,0,.6);font-size:.8rem;z-index:1}\
+video{width:100%;aspect-ratio:16/9;display:block}\
+figure.error figcaption{background:#a00}";
+const SCRIPT: &str = "document.querySelectorAll('video[data-src]').forEach(function (video) {
+  var src = video.dataset.src;
+  var figure = video.parentNode;
+  if (video.canPlayType('application/vnd.apple.mpegurl')) {
+    video.src = src;
+    video.addEventListener('error', function () { figure.classList.add('error'); });
+  } else if (window.Hls && Hls.isSupported()) {
+    var hls = new Hls({ liveDurationInfinity: true });
+    hls.on(Hls.Events.ERROR, function (_, data) {
+      if (!data.fatal) return;
+      figure.classList.add('error');
+      // 直播尚未开始或暂时中断时稍后重试。
+      setTimeout(function () { figure.classList.remove('error'); hls.loadSource(src); hls.startLoad(); }, 10000);
+    });
+    hls.loadSource(src);
+    hls.attachMedia(video);
+  } else {
+    figure.classList.add('error');
+  }
+});";
+impl MultiView {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            columns: None,
+            player_script: HLS_JS_URL.to_owned(),
+            tiles: Vec::new(),
+        }
+    }
+    /// 每行的列数，默认取能排成方阵的最小值。
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = Some(columns.max(1));
+        self
+    }
+    /// hls.js 的地址，可改为本地文件以离线使用。
+    pub fn player_script(mut self, url: impl Into<String>) -> Self {
+        self.player_script = url.into();
+        self
+    }
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+    pub fn add(&mut self, title: impl Into<String>, url: impl Into<String>) {
+        self.tiles.push(Tile {
+            title: title.into(),
+            url: url.into(),
+        });
+    }
+    /// 添加 `video_path` 中属于 `kinds` 的各路视频，见 [`VideoPath::titled_streams`].
+    ///
+    /// 返回添加的数目。
+    pub fn add_video_path(
+        &mut self,
+        name: &str,
+        video_path: &VideoPath,
+        kinds: &[StreamKind],
+    ) -> usize {
+        let count = self.tiles.len();
+        for (_, url, title) in video_path.titled_streams(name, kinds) {
+            self.add(title, url);
+        }
+        self.tiles.len() - count
+    }
+    /// 获取教室的直播地址并添加，见 [`MultiView::add_video_path`].
+    pub fn add_room(
+        &mut self,
+        client: &impl Client,
+        room: &Room,
+        kinds: &[StreamKind],
+    ) -> Result<usize, Box<ureq::Error>> {
+        let video_path = room.get_live_video_path(client)?;
+        Ok(self.add_video_path(room.name(), &video_path, kinds))
+    }
+    pub fn render(&self) -> String {
+        let columns = self.columns.unwrap_or_else(|| {
+            (1..)
+                .find(|c| c * c >= self.tiles.len())
+                .unwrap_or(1)
+                .max(1)
+        });
+        let title = escape_xml(&self.title);
+        let mut html = String::from("<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n");
+        html.push_str("<meta charset=\"utf-8\">\n");
+        html.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+        let _ = writeln!(html, "<title>{title}</title>");
+        let _ = writeln!(html, "<style>{STYLE}</style>");
+        let _ = writeln!(
+            html,
+            "<script src=\"{}\"></script>",
+            escape_xml(&self.player_script)
+        );
+        html.push_str("</head>\n<body>\n");
+        let _ = writeln!(html, "<h1>{title}</h1>");
+        let _ = writeln!(
+            html,
+            "<main style=\"grid-template-columns:repeat({columns},1fr)\">"
+        );
+        for tile in &self.tiles {
+            let _ = writeln!(
+                html,
+                "<figure><figcaption>{}</figcaption><video data-src=\"{}\" muted autoplay controls playsinline></video></figure>",
+                escape_xml(&tile.title),
+                escape_xml(&tile.url)
+            );
+        }
+        html.push_str("</main>\n");
+        let _ = writeln!(html, "<script>\n{SCRIPT}\n</script>");
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_multi_view() {
+        let video_path: VideoPath = serde_json::from_str(
+            r#"{"ppt_video":"http://a/p.m3u8?a=1&b=2","teacher_full":"","teacher_track":"http://a/t.m3u8","student_full":null}"#,
+        )
+        .unwrap();
+        let mut view = MultiView::new("<B-203>");
+        let kinds = [
+            StreamKind::TeacherTrack,
+            StreamKind::TeacherFull,
+            StreamKind::PptVideo,
+        ];
+        assert_eq!(view.add_video_path("B-203", &video_path, &kinds), 2);
+        view.add("C-101", "http://a/c.m3u8");
+        let html = view.render();
+        assert!(html.contains("<title>&lt;B-203&gt;</title>"));
+        assert!(html.contains("repeat(2,1fr)"));
+        assert!(html.contains("data-src=\"http://a/p.m3u8?a=1&amp;b=2\""));
+        assert_eq!(view.tiles()[0].title(), "B-203 teacher_track");
+        assert_eq!(html.matches("<figure>").count(), 3);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 直播课表的 iCalendar (RFC 5545) 导出。
+
+use crate::{Live, Room, SessionClient, Timetable};
+use chrono::{Days, FixedOffset, NaiveDate, TimeZone, Utc};
+use log::warn;
+use std::{collections::HashMap, ops::RangeInclusive};
+
+/// 学校所在时区（UTC+8）。
+const SCHOOL_OFFSET_SECS: i32 = 8 * 3600;
+
+/// 课表中的一节课。
+#[derive(Debug, Clone)]
+pub struct CalendarEntry {
+    term_year: i32,
+    term: i32,
+    week: i64,
+    live: Live,
+    room: Option<Room>,
+}
+impl CalendarEntry {
+    pub fn new(term_year: i32, term: i32, week: i64, live: Live, room: Option<Room>) -> Self {
+        Self {
+            term_year,
+            term,
+            week,
+            live,
+            room,
+        }
+    }
+    pub fn week(&self) -> i64 {
+        self.week
+    }
+    pub fn live(&self) -> &Live {
+        &self.live
+    }
+    pub fn room(&self) -> Option<&Room> {
+        self.room.as_ref()
+    }
+    /// 重复导入时据此更新而非新增日程。
+    pub fn uid(&self) -> String {
+        format!(
+            "{}-{}-{}-{}-{}-{}@xddcc",
+            self.term_year,
+            self.term,
+            self.week,
+            self.live.get_week_day(),
+            self.live.get_jie(),
+            self.live.get_id()
+        )
+    }
+}
+/// 获取一个学期中 `weeks` 各周的直播，并查询其所在教室。
+pub fn fetch_semester(
+    session: &impl SessionClient,
+    term_year: i32,
+    term: i32,
+    weeks: RangeInclusive<i64>,
+) -> Result<Vec<CalendarEntry>, Box<ureq::Error>> {
+    let mut rooms: HashMap<i64, Option<Room>> = HashMap::new();
+    let mut entries = Vec::new();
+    for week in weeks {
+        for live in Live::get_week_lives(session, week, term_year, term)? {
+            let room = rooms
+                .entry(live.get_id())
+                .or_insert_with(|| match Room::get_rooms(session, live.get_id()) {
+                    Ok(room) => room,
+                    Err(e) => {
+                        warn!("教室获取错误：{e}.");
+                        None
+                    }
+                })
+                .clone();
+            entries.push(CalendarEntry::new(term_year, term, week, live, room));
+        }
+    }
+    Ok(entries)
+}
+/// 按 RFC 5545 转义文本。
+fn escape_text(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+/// 写入一行内容，超过 75 字节时折行。
+fn push_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+/// 生成日历。`semester_start` 为第一周周一的日期，可由 [`crate::term_begin_date`] 获取。
+pub fn render_calendar(
+    name: &str,
+    entries: &[CalendarEntry],
+    semester_start: NaiveDate,
+    timetable: &Timetable,
+) -> String {
+    let offset = FixedOffset::east_opt(SCHOOL_OFFSET_SECS).unwrap();
+    let utc = |date: NaiveDate, time| {
+        offset
+            .from_local_datetime(&date.and_time(time))
+            .single()
+            .map(|t| t.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string())
+    };
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(
+        &mut ics,
+        "PRODID:-//xddcc//XiDian Distant Courses Collector//ZH",
+    );
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape_text(name)));
+    for entry in entries {
+        let Some((start, end)) = timetable.range(entry.live.get_jie()) else {
+            warn!("未知的节次：{}.", entry.live.get_jie());
+            continue;
+        };
+        let days = (entry.week - 1) * 7 + entry.live.get_week_day() as i64 - 1;
+        let Some(date) = u64::try_from(days)
+            .ok()
+            .and_then(|d| semester_start.checked_add_days(Days::new(d)))
+        else {
+            continue;
+        };
+        let (Some(start), Some(end)) = (utc(date, start), utc(date, end)) else {
+            continue;
+        };
+        let location = entry
+            .room
+            .as_ref()
+            .map(|r| r.name())
+            .unwrap_or(entry.live.place().trim());
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}", entry.uid()));
+        push_line(&mut ics, &format!("DTSTAMP:{stamp}"));
+        push_line(&mut ics, &format!("DTSTART:{start}"));
+        push_line(&mut ics, &format!("DTEND:{end}"));
+        let summary = format!("{location} 第 {} 节", entry.live.get_jie());
+        push_line(&mut ics, &format!("SUMMARY:{}", escape_text(&summary)));
+        push_line(&mut ics, &format!("LOCATION:{}", escape_text(location)));
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_calendar() {
+        let live: Live =
+            serde_json::from_str(r#"{"place":"B-203, 东区","id":7,"weekDay":2,"jie":3}"#).unwrap();
+        let entry = CalendarEntry::new(2024, 1, 2, live, None);
+        let start = NaiveDate::from_ymd_opt(2024, 9, 2).unwrap();
+        let ics = render_calendar("课表", &[entry], start, &Timetable::default());
+        assert!(ics.contains("UID:2024-1-2-2-3-7@xddcc\r\n"));
+        // 第二周周二 10:25 (UTC+8).
+        assert!(ics.contains("DTSTART:20240910T022500Z\r\n"));
+        assert!(ics.contains("DTEND:20240910T040000Z\r\n"));
+        assert!(ics.contains("LOCATION:B-203\\, 东区\r\n"));
+        assert!(!ics.contains("URL:"));
+        assert!(ics.split("\r\n").all(|l| l.len() <= 75));
+    }
+}
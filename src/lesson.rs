@@ -17,8 +17,9 @@ use super::tools::{
     arc_into_inner_error_handler, json_parsing_error_handler, mutex_into_inner_error_handler,
     VideoPath,
 };
-use crate::{ProgressState, ProgressTracker, ProgressTrackerHolder};
-use cxlib_types::Session;
+use crate::{
+    Client, ProgressItem, ProgressState, ProgressTracker, ProgressTrackerHolder, SessionClient,
+};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -44,24 +45,39 @@ impl Lesson {
         self.id
     }
     pub fn get_recording_url(
-        session: &Session,
+        client: &impl Client,
         live_id: i64,
     ) -> Result<VideoPath, Box<ureq::Error>> {
-        crate::tools::get_recording_live_video_path(session, live_id)
+        crate::tools::get_recording_live_video_path(client, live_id)
     }
-    pub fn get_all_lessons(session: &Session, live_id: i64) -> Result<Vec<i64>, Box<ureq::Error>> {
+    /// 获取课程的所有课次，按开始时间排序。
+    pub fn get_lessons(
+        session: &impl SessionClient,
+        live_id: i64,
+    ) -> Result<Vec<Lesson>, Box<ureq::Error>> {
         let mut lessons: Vec<Lesson> = crate::protocol::list_single_course(session, live_id)?
             .into_body()
             .read_json()
             .unwrap_or_else(json_parsing_error_handler);
         lessons.sort_by_key(|l| l.get_start_time());
+        Ok(lessons)
+    }
+    pub fn get_all_lessons(
+        session: &impl SessionClient,
+        live_id: i64,
+    ) -> Result<Vec<i64>, Box<ureq::Error>> {
+        let lessons = Lesson::get_lessons(session, live_id)?;
         Ok(lessons.into_iter().map(|l| l.get_live_id()).collect())
     }
-    pub fn get_recording_lives<P: ProgressTracker + 'static>(
-        session: &Session,
+    pub fn get_recording_lives<S, P>(
+        session: &S,
         live_id: i64,
         multi: &impl ProgressTrackerHolder<P>,
-    ) -> Result<HashMap<i64, VideoPath>, Box<ureq::Error>> {
+    ) -> Result<HashMap<i64, VideoPath>, Box<ureq::Error>>
+    where
+        S: SessionClient + Clone + Send + 'static,
+        P: ProgressTracker + 'static,
+    {
         let lessons: Vec<Lesson> = crate::protocol::list_single_course(session, live_id)?
             .into_body()
             .read_json()
@@ -69,7 +85,8 @@ impl Lesson {
         let total = lessons.len();
         let thread_count = total / 64;
         let rest_count = total % 64;
-        let pb = multi.init(total as u64, ProgressState::GetRecordingLives);
+        let state = ProgressState::GetRecordingLives { live_id };
+        let pb = multi.init(total as u64, state.clone());
         let pb = Arc::new(Mutex::new(pb));
         let paths = Arc::new(Mutex::new(HashMap::new()));
         let mut handles = Vec::new();
@@ -88,8 +105,16 @@ impl Lesson {
             }
             let handle = std::thread::spawn(move || {
                 for lesson in lessons_ {
-                    if let Ok(path) = Lesson::get_recording_url(&session, lesson.get_live_id()) {
-                        paths.lock().unwrap().insert(lesson.get_start_time(), path);
+                    let item = ProgressItem::Lesson {
+                        lesson_id: lesson.get_live_id(),
+                        start_time: lesson.get_start_time(),
+                    };
+                    match Lesson::get_recording_url(&session, lesson.get_live_id()) {
+                        Ok(path) => {
+                            paths.lock().unwrap().insert(lesson.get_start_time(), path);
+                            pb.lock().unwrap().on_item(Ok(()), &item);
+                        }
+                        Err(e) => pb.lock().unwrap().on_item(Err(&*e), &item),
                     }
                     pb.lock().unwrap().inc(1);
                 }
@@ -107,7 +132,7 @@ impl Lesson {
             .unwrap_or_else(arc_into_inner_error_handler)
             .into_inner()
             .unwrap_or_else(mutex_into_inner_error_handler);
-        pb.finish(ProgressState::GetRecordingLives);
+        pb.finish(state);
         multi.remove_progress(&pb);
         Ok(paths)
     }
@@ -13,14 +13,43 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(feature = "cli")]
+pub mod cli;
+mod client;
+pub mod config;
+#[cfg(feature = "download")]
+pub mod download;
+pub mod event;
+pub mod feed;
+pub mod hls;
+pub mod html;
+pub mod ics;
 pub mod lesson;
 mod live;
+pub mod manifest;
+pub mod monitor;
+pub mod output;
+pub mod playlist;
 mod progress;
 pub mod protocol;
+#[cfg(feature = "download")]
+pub mod record;
+#[cfg(feature = "server")]
+pub mod relay;
 mod room;
+#[cfg(feature = "download")]
+pub mod schedule;
+#[cfg(feature = "server")]
+pub mod server;
+mod session_pool;
 mod tools;
+mod trackers;
+pub mod ts;
 
+pub use client::*;
 pub use live::*;
 pub use progress::*;
 pub use room::*;
+pub use session_pool::*;
 pub use tools::*;
+pub use trackers::*;
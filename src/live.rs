@@ -15,10 +15,12 @@
 
 use crate::{
     room::Room,
+    session_pool::is_session_error,
+    tools::TermError,
     tools::{json_parsing_error_handler, VideoPath},
-    ProgressState, ProgressTracker, ProgressTrackerHolder,
+    ProgressItem, ProgressState, ProgressTracker, ProgressTrackerHolder, ServerSession,
+    SessionClient, SessionPool,
 };
-use cxlib_types::Session;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -41,16 +43,30 @@ impl Live {
     pub fn get_jie(&self) -> i32 {
         self.jie
     }
+    pub fn place(&self) -> &str {
+        self.place.as_str()
+    }
+    /// 获取某周的所有直播。
+    pub fn get_week_lives(
+        session: &impl SessionClient,
+        week: i64,
+        term_year: i32,
+        term: i32,
+    ) -> Result<Vec<Live>, Box<ureq::Error>> {
+        Ok(
+            crate::protocol::list_student_course_live_page(session, week, term_year, term)?
+                .into_body()
+                .read_json::<Vec<Live>>()
+                .unwrap_or_else(json_parsing_error_handler),
+        )
+    }
     pub fn get_lives(
-        session: &Session,
+        session: &impl SessionClient,
         week: i64,
         term_year: i32,
         term: i32,
     ) -> Result<HashMap<String, i64>, Box<ureq::Error>> {
-        let vec = crate::protocol::list_student_course_live_page(session, week, term_year, term)?
-            .into_body()
-            .read_json::<Vec<Live>>()
-            .unwrap_or_else(json_parsing_error_handler);
+        let vec = Live::get_week_lives(session, week, term_year, term)?;
         let mut map = HashMap::new();
         for i in vec {
             map.insert(i.place, i.id);
@@ -58,17 +74,14 @@ impl Live {
         Ok(map)
     }
     fn get_lives_by_time(
-        session: &Session,
+        session: &impl SessionClient,
         term_year: i32,
         term: i32,
         week: i64,
         week_day: u32,
         jie: i32,
     ) -> Result<Option<Live>, Box<ureq::Error>> {
-        let vec = crate::protocol::list_student_course_live_page(session, week, term_year, term)?
-            .into_body()
-            .read_json::<Vec<Live>>()
-            .unwrap_or_else(json_parsing_error_handler);
+        let vec = Live::get_week_lives(session, week, term_year, term)?;
         let iter = vec
             .into_iter()
             .filter(|live| (live.get_week_day() == week_day) && (live.get_jie() >= jie));
@@ -76,74 +89,116 @@ impl Live {
         vec.sort_by_key(|live| live.get_jie());
         Ok(vec.first().cloned())
     }
-    pub fn get_lives_now<
-        'a,
-        Iter: Iterator<Item = &'a Session> + Clone,
-        P: ProgressTracker + 'static,
-    >(
-        sessions: Iter,
+    /// 获取各会话当前（或上一节）的直播。
+    ///
+    /// 每个会话查询自己的课表，失效的会话会在 `pool` 中被标记为不可用；
+    /// 教室等公共查询在可用的会话间轮转，直播地址则匿名获取。
+    pub fn get_lives_now<'a, P: ProgressTracker + 'static>(
+        pool: &'a SessionPool,
         previous: bool,
         multi: &impl ProgressTrackerHolder<P>,
     ) -> HashMap<&'a str, (&'a str, Room, VideoPath)> {
-        let sessions = sessions.collect::<Vec<_>>();
+        Live::get_lives_by_jie(pool, crate::tools::now_to_jie(previous), multi).unwrap_or_else(
+            |e| {
+                warn!("{e}.");
+                HashMap::new()
+            },
+        )
+    }
+    /// 同 [`Live::get_lives_now`], 但获取今天从第 `jie` 节开始的直播，
+    /// 可配合 [`crate::Timetable::now_to_jie`] 使用自定义的作息时间。
+    ///
+    /// 无法确定当前的学期与周次时返回错误。
+    pub fn get_lives_by_jie<'a, P: ProgressTracker + 'static>(
+        pool: &'a SessionPool,
+        jie: i32,
+        multi: &impl ProgressTrackerHolder<P>,
+    ) -> Result<HashMap<&'a str, (&'a str, Room, VideoPath)>, TermError> {
+        let sessions = pool.sessions();
         let total = sessions.len() as u64;
         let data_time = chrono::DateTime::<chrono::Local>::from(std::time::SystemTime::now());
-        let mut term_year = 0;
-        let mut term = 0;
-        let mut week = 0;
-        let mut first = true;
+        // 学期信息与直播地址均无需登录。
+        let client = pool.anonymous_client();
+        let (term_year, term, week) = crate::tools::term_year_detail(&client)?;
         let week_day = chrono::Datelike::weekday(&data_time).number_from_monday();
         // `Session` 的 `Hash` 实现不涉及内部可变的字段。
         #[allow(clippy::mutable_key_type)]
-        let mut lives_map: HashMap<&Session, Live> = HashMap::new();
-        let pb = multi.init(total, ProgressState::GetLiveIds);
-        for session in sessions.clone() {
+        let mut lives_map: HashMap<&ServerSession, Live> = HashMap::new();
+        let state = ProgressState::GetLiveIds {
+            sessions: sessions.len(),
+        };
+        let operation = multi.init(2, ProgressState::GetLivesNow);
+        let pb = multi.init_child(&operation, total, state.clone());
+        for session in sessions {
             if !pb.go_on() {
                 debug!("list_rooms/get_all_live_id: break.");
                 break;
             }
-            if first {
-                (term_year, term, week) = crate::tools::term_year_detail(session);
-                first = false;
-            }
-            let jie = crate::tools::now_to_jie(previous);
-            let live = Live::get_lives_by_time(session, term_year, term, week, week_day, jie);
-            if let Ok(Some(live)) = live {
-                lives_map.insert(session, live);
+            let item = ProgressItem::Week {
+                uid: session.uid().to_owned(),
+                term_year,
+                term,
+                week,
+            };
+            match Live::get_lives_by_time(session, term_year, term, week, week_day, jie) {
+                Ok(live) => {
+                    if let Some(live) = live {
+                        lives_map.insert(session, live);
+                    }
+                    pb.on_item(Ok(()), &item);
+                }
+                Err(e) => {
+                    warn!("课表获取错误：{e}.");
+                    if is_session_error(&e) {
+                        pool.mark_unhealthy(session);
+                    }
+                    pb.on_item(Err(&*e), &item);
+                }
             }
             pb.inc(1)
         }
-        pb.finish(ProgressState::GetLiveIds);
+        pb.finish(state);
         multi.remove_progress(&pb);
+        operation.inc(1);
         let mut lives = HashSet::new();
         for live in lives_map.values() {
             lives.insert(live.get_id());
         }
         let mut rooms = HashMap::new();
-        let pb = multi.init(total, ProgressState::GetLiveUrls);
-        pb.inc(0);
-        if let Some(session) = sessions.clone().into_iter().next() {
-            for live in lives {
-                if !pb.go_on() {
-                    debug!("list_rooms/id_to_rooms: break.");
-                    break;
-                }
-                match Room::get_rooms(session, live) {
-                    Ok(room) => {
-                        if let Some(room) = room {
-                            pb.inc(1);
-                            let video_path = room.get_live_video_path(session);
-                            pb.inc(1);
-                            rooms.insert(live, (room, video_path));
-                        } else {
-                            pb.inc(2);
+        // 每个直播号依次查询教室与直播地址。
+        let pb = multi.init_child(
+            &operation,
+            lives.len() as u64 * 2,
+            ProgressState::GetLiveUrls,
+        );
+        for live in lives {
+            if !pb.go_on() {
+                debug!("list_rooms/id_to_rooms: break.");
+                break;
+            }
+            let room_item = ProgressItem::Room { live_id: live };
+            match pool.with_failover(|session| Room::get_rooms(session, live)) {
+                Ok(room) => {
+                    pb.on_item(Ok(()), &room_item);
+                    if let Some(room) = room {
+                        pb.inc(1);
+                        let video_path = room.get_live_video_path(&client);
+                        let url_item = ProgressItem::LiveUrl { live_id: live };
+                        match &video_path {
+                            Ok(_) => pb.on_item(Ok(()), &url_item),
+                            Err(e) => pb.on_item(Err(&**e), &url_item),
                         }
-                    }
-                    Err(e) => {
-                        warn!("教室获取错误：{e}.");
+                        pb.inc(1);
+                        rooms.insert(live, (room, video_path));
+                    } else {
                         pb.inc(2);
                     }
                 }
+                Err(e) => {
+                    warn!("教室获取错误：{e}.");
+                    pb.on_item(Err(&*e), &room_item);
+                    pb.inc(2);
+                }
             }
         }
         let mut results = HashMap::new();
@@ -164,6 +219,9 @@ impl Live {
         }
         pb.finish(ProgressState::GetLiveUrls);
         multi.remove_progress(&pb);
-        results
+        operation.inc(1);
+        operation.finish(ProgressState::GetLivesNow);
+        multi.remove_progress(&operation);
+        Ok(results)
     }
 }
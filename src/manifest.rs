@@ -0,0 +1,426 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 供 aria2 等下载工具使用的录播下载清单。
+//!
+//! 清单可以是 aria2 的输入文件（`aria2c -i`），也可以是每行一个地址的纯文本列表。
+//! 纯文本列表不含文件名与请求头，文件名与课次的对应关系见 [`DownloadManifest::sidecar`].
+//!
+//! 清单中的地址即录播地址，通常是 HLS 播放列表（`.m3u8`）。aria2 只会下载播放列表本身，
+//! 这类地址应交给 `ffmpeg`, `yt-dlp` 等支持 HLS 的下载工具，或使用 [`crate::download`] 下载分片。
+//! [`ManifestEntry::is_playlist`] 可用于区分这类条目。
+
+use crate::{
+    lesson::Lesson,
+    output::{OutputError, PathTemplate, TemplateVars},
+    Client, StreamKind, VideoPath,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{Display, Formatter, Write},
+    str::FromStr,
+};
+use ureq::{http::Uri, Agent, CookieJar};
+
+/// 默认的文件名模板，可用的变量见 [`TemplateVars`].
+pub const DEFAULT_TEMPLATE: &str = "{course}_{date}_{stream}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ManifestFormat {
+    /// aria2 的输入文件。
+    #[default]
+    Aria2,
+    /// 每行一个地址。
+    UrlList,
+}
+impl ManifestFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManifestFormat::Aria2 => "aria2",
+            ManifestFormat::UrlList => "urls",
+        }
+    }
+}
+impl Display for ManifestFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseManifestFormatError(String);
+impl Display for ParseManifestFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "未知的清单格式：`{}`.", self.0)
+    }
+}
+impl std::error::Error for ParseManifestFormatError {}
+impl FromStr for ManifestFormat {
+    type Err = ParseManifestFormatError;
+
+    /// 接受 `aria2`, `urls` 与 `txt`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "aria2" => Ok(ManifestFormat::Aria2),
+            "urls" | "txt" => Ok(ManifestFormat::UrlList),
+            _ => Err(ParseManifestFormatError(s.to_owned())),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    #[serde(skip)]
+    file: String,
+    url: String,
+    lesson_id: i64,
+    start_time: i64,
+    stream: StreamKind,
+    /// 发往 `url` 的 Cookie, 见 [`DownloadManifest::cookies`].
+    #[serde(skip)]
+    cookie: Option<String>,
+}
+impl ManifestEntry {
+    /// 相对于下载目录的文件名，可能包含子目录。
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn lesson_id(&self) -> i64 {
+        self.lesson_id
+    }
+    pub fn start_time(&self) -> i64 {
+        self.start_time
+    }
+    pub fn stream(&self) -> StreamKind {
+        self.stream
+    }
+    pub fn cookie(&self) -> Option<&str> {
+        self.cookie.as_deref()
+    }
+    /// 地址是否为 HLS 播放列表，见模块文档。
+    pub fn is_playlist(&self) -> bool {
+        url_extension(&self.url).eq_ignore_ascii_case(".m3u8")
+    }
+}
+/// 地址路径中的扩展名，如 `.mp4`, 没有时为空。
+fn url_extension(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    match name.rfind('.') {
+        Some(i)
+            if i > 0
+                && name.len() - i <= 6
+                && name[i + 1..].chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            &name[i..]
+        }
+        _ => "",
+    }
+}
+/// `cookie` 的字符串形式是否带有名为 `name` 的属性，如 `Domain`.
+fn has_attribute(cookie: &str, name: &str) -> bool {
+    cookie.split(';').skip(1).any(|attribute| {
+        let key = attribute.split('=').next().unwrap_or_default();
+        key.trim().eq_ignore_ascii_case(name)
+    })
+}
+/// `jar` 中发往 `url` 的 Cookie, 以 `; ` 连接，没有时返回 `None`.
+///
+/// 按 RFC 6265 匹配域名、路径与 `Secure`: 未设置 `Domain` 的 Cookie 只发往设置它的主机，
+/// 路径较长的 Cookie 在前。
+fn request_cookies(jar: &CookieJar<'_>, url: &str) -> Option<String> {
+    let uri: Uri = url.parse().ok()?;
+    let host = uri.host()?.to_ascii_lowercase();
+    let secure = uri.scheme_str() == Some("https");
+    let path = uri.path();
+    // 主机及其各级父域名。
+    let domains = std::iter::successors(Some(host.as_str()), |d| d.split_once('.').map(|(_, p)| p))
+        .filter(|d| d.contains('.') || *d == host)
+        .collect::<Vec<_>>();
+    // 路径的各级前缀，由长到短。
+    let mut paths = path
+        .match_indices('/')
+        .flat_map(|(i, _)| [&path[..i], &path[..=i]])
+        .chain([path])
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>();
+    paths.sort_by_key(|p| std::cmp::Reverse(p.len()));
+    paths.dedup();
+    let names = jar
+        .iter()
+        .map(|cookie| cookie.name().to_owned())
+        .collect::<HashSet<_>>();
+    let mut cookies = Vec::new();
+    for path in &paths {
+        for domain in &domains {
+            for name in &names {
+                let Some(cookie) = jar.get(domain, path, name) else {
+                    continue;
+                };
+                let attributes = cookie.to_string();
+                if (*domain != host && !has_attribute(&attributes, "Domain"))
+                    || (!secure && has_attribute(&attributes, "Secure"))
+                {
+                    continue;
+                }
+                cookies.push(format!("{}={}", cookie.name(), cookie.value()));
+            }
+        }
+    }
+    (!cookies.is_empty()).then(|| cookies.join("; "))
+}
+/// 录播下载清单，条目按添加的顺序排列。
+#[derive(Debug, Clone)]
+pub struct DownloadManifest {
+    course: String,
+    template: PathTemplate,
+    headers: Vec<(String, String)>,
+    cookies: Option<Agent>,
+    entries: Vec<ManifestEntry>,
+    files: HashSet<String>,
+}
+impl DownloadManifest {
+    /// `course` 为模板中的 `{course}`, 文件名模板默认为 [`DEFAULT_TEMPLATE`].
+    pub fn new(course: impl Into<String>) -> Self {
+        Self {
+            course: course.into(),
+            template: PathTemplate::new(DEFAULT_TEMPLATE),
+            headers: Vec::new(),
+            cookies: None,
+            entries: Vec::new(),
+            files: HashSet::new(),
+        }
+    }
+    /// 文件名模板，扩展名会根据地址自动添加。
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = PathTemplate::new(template);
+        self
+    }
+    /// 下载时附带的请求头，仅写入 aria2 的输入文件。
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+    /// 为之后添加的条目附带 `client` 中发往该地址的 Cookie, 如 [`cxlib_types::Session`] 的登录状态。
+    ///
+    /// 每个条目只附带域名与路径匹配其地址的 Cookie, 仅写入 aria2 的输入文件。
+    /// Cookie 以明文写入清单，应妥善保管清单文件。
+    pub fn cookies(mut self, client: &impl Client) -> Self {
+        self.cookies = Some(client.agent().clone());
+        self
+    }
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+    /// 文件名重复时在扩展名前加上序号。
+    fn unique_file(&mut self, stem: &str, extension: &str) -> String {
+        let mut file = format!("{stem}{extension}");
+        let mut n = 2;
+        while self.files.contains(&file) {
+            file = format!("{stem}_{n}{extension}");
+            n += 1;
+        }
+        self.files.insert(file.clone());
+        file
+    }
+    /// 按开始时间的顺序添加 `lessons` 的录播，`recordings` 为 [`Lesson::get_recording_lives`] 的结果，
+    /// 两者按开始时间对应。每个课次按 `kinds` 的顺序添加存在的各路视频。
+    ///
+    /// 返回添加的数目。
+    pub fn add_recordings(
+        &mut self,
+        lessons: &[Lesson],
+        recordings: &HashMap<i64, VideoPath>,
+        kinds: &[StreamKind],
+    ) -> Result<usize, OutputError> {
+        let mut lessons = lessons.iter().collect::<Vec<_>>();
+        lessons.sort_by_key(|lesson| lesson.get_start_time());
+        let mut count = 0;
+        for lesson in lessons {
+            let Some(video_path) = recordings.get(&lesson.get_start_time()) else {
+                continue;
+            };
+            for kind in kinds {
+                let Some(url) = video_path.get(*kind) else {
+                    continue;
+                };
+                let vars = TemplateVars::new()
+                    .set("course", &self.course)
+                    .lesson(lesson)
+                    .stream(*kind);
+                let stem = self.template.render(&vars)?;
+                // aria2 的 `out` 选项使用 `/` 分隔子目录。
+                let stem = stem
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let extension = url_extension(url);
+                let stem = stem.strip_suffix(extension).unwrap_or(&stem).to_owned();
+                let file = self.unique_file(&stem, extension);
+                let cookie = self
+                    .cookies
+                    .as_ref()
+                    .and_then(|agent| request_cookies(&agent.cookie_jar_lock(), url));
+                self.entries.push(ManifestEntry {
+                    file,
+                    url: url.to_owned(),
+                    lesson_id: lesson.get_live_id(),
+                    start_time: lesson.get_start_time(),
+                    stream: *kind,
+                    cookie,
+                });
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    pub fn to_aria2(&self) -> String {
+        let mut input = String::new();
+        for entry in &self.entries {
+            let _ = writeln!(input, "{}", entry.url);
+            let _ = writeln!(input, "  out={}", entry.file);
+            for (name, value) in &self.headers {
+                let _ = writeln!(input, "  header={name}: {value}");
+            }
+            if let Some(cookie) = &entry.cookie {
+                let _ = writeln!(input, "  header=Cookie: {cookie}");
+            }
+        }
+        input
+    }
+    pub fn to_url_list(&self) -> String {
+        let mut list = String::new();
+        for entry in &self.entries {
+            let _ = writeln!(list, "{}", entry.url);
+        }
+        list
+    }
+    pub fn render(&self, format: ManifestFormat) -> String {
+        match format {
+            ManifestFormat::Aria2 => self.to_aria2(),
+            ManifestFormat::UrlList => self.to_url_list(),
+        }
+    }
+    /// 以文件名为键的 JSON 对照表，值包含课次编号、开始时间、视频类型与地址。
+    pub fn sidecar(&self) -> String {
+        let files = self
+            .entries
+            .iter()
+            .map(|entry| (entry.file.as_str(), entry))
+            .collect::<BTreeMap<_, _>>();
+        serde_json::to_string_pretty(&files).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_download_manifest() {
+        let lessons: Vec<Lesson> = serde_json::from_str(
+            r#"[{"startTime":{"time":1700000000000},"id":2},{"startTime":{"time":1700000100000},"id":3},{"startTime":{"time":1600000000000},"id":1}]"#,
+        )
+        .unwrap();
+        let video_path: VideoPath = serde_json::from_str(
+            r#"{"ppt_video":"http://a/p.mp4?t=1","teacher_full":null,"teacher_track":"http://a/t","student_full":""}"#,
+        )
+        .unwrap();
+        let recordings = HashMap::from([
+            (1_700_000_000_000, video_path.clone()),
+            (1_700_000_100_000, video_path.clone()),
+            (1_500_000_000_000, video_path),
+        ]);
+        let kinds = [StreamKind::PptVideo, StreamKind::StudentFull];
+        let mut manifest = DownloadManifest::new("课/程").header("Referer", "http://a/");
+        assert_eq!(
+            manifest
+                .add_recordings(&lessons, &recordings, &kinds)
+                .unwrap(),
+            2
+        );
+        let date = TemplateVars::new().lesson(&lessons[0]);
+        let date = date.get("date").unwrap();
+        let files = manifest
+            .entries()
+            .iter()
+            .map(ManifestEntry::file)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                format!("课_程_{date}_ppt_video.mp4"),
+                format!("课_程_{date}_ppt_video_2.mp4")
+            ]
+        );
+        assert_eq!(
+            manifest.to_aria2().lines().collect::<Vec<_>>()[..3],
+            [
+                "http://a/p.mp4?t=1",
+                &format!("  out={}", files[0]),
+                "  header=Referer: http://a/"
+            ]
+        );
+        assert_eq!(manifest.to_url_list().lines().count(), 2);
+        let sidecar: serde_json::Value = serde_json::from_str(&manifest.sidecar()).unwrap();
+        assert_eq!(sidecar[&files[1]]["lesson_id"], 3);
+        assert!(!manifest.entries()[0].is_playlist());
+        assert_eq!(url_extension("http://a/t"), "");
+        assert_eq!(url_extension("http://a.b/x/index.m3u8#f"), ".m3u8");
+    }
+    #[test]
+    fn test_request_cookies() {
+        let agent = Agent::new_with_defaults();
+        let mut jar = agent.cookie_jar_lock();
+        for (cookie, url) in [
+            (
+                "a=1; Domain=chaoxing.com; Path=/",
+                "http://newesxidian.chaoxing.com/",
+            ),
+            ("b=2", "http://newesxidian.chaoxing.com/"),
+            ("c=3; Path=/live", "http://newesxidian.chaoxing.com/"),
+            ("d=4; Secure", "https://newesxidian.chaoxing.com/"),
+            ("e=5", "http://example.com/"),
+        ] {
+            let uri: Uri = url.parse().unwrap();
+            jar.insert(ureq::Cookie::parse(cookie, &uri).unwrap(), &uri)
+                .unwrap();
+        }
+        let cookies = |url| request_cookies(&jar, url);
+        assert_eq!(
+            cookies("http://vod.chaoxing.com/x/p.m3u8").as_deref(),
+            Some("a=1")
+        );
+        let cookies_for_live = cookies("http://newesxidian.chaoxing.com/live/x.m3u8").unwrap();
+        let mut sent = cookies_for_live.split("; ").collect::<Vec<_>>();
+        assert_eq!(sent[0], "c=3");
+        sent.sort();
+        assert_eq!(sent, ["a=1", "b=2", "c=3"]);
+        let mut sent = cookies("https://newesxidian.chaoxing.com/")
+            .unwrap()
+            .split("; ")
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        sent.sort();
+        assert_eq!(sent, ["a=1", "b=2", "d=4"]);
+        assert_eq!(cookies("http://other.org/"), None);
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 录播发布情况的监视。
+//!
+//! 录播会在下课后一段时间才出现在服务器上。监视器记录尚无录播的课次，
+//! 按退避策略重复查询，任一路视频可用时产生 [`MonitorEvent::Available`] 事件。
+
+use crate::{
+    lesson::Lesson, Client, ProgressItem, ProgressState, ProgressTracker, ProgressTrackerHolder,
+    SessionClient, VideoPath,
+};
+use chrono::Local;
+use log::{debug, warn};
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct MonitorOptions {
+    /// 首次重新查询前的等待时间。
+    pub initial_interval: Duration,
+    /// 查询间隔的上限，每次查询后间隔加倍，直至该值。
+    pub max_interval: Duration,
+    /// 自课次开始起超过该时长仍无录播时放弃。
+    pub give_up_after: Duration,
+}
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        Self {
+            initial_interval: Duration::from_secs(5 * 60),
+            max_interval: Duration::from_secs(60 * 60),
+            give_up_after: Duration::from_secs(3 * 24 * 60 * 60),
+        }
+    }
+}
+#[derive(Serialize, Debug, Clone)]
+pub enum MonitorEvent {
+    /// 录播已发布。
+    Available {
+        lesson_id: i64,
+        start_time: i64,
+        video_path: VideoPath,
+    },
+    /// 超过 [`MonitorOptions::give_up_after`] 仍无录播。
+    GaveUp { lesson_id: i64, start_time: i64 },
+}
+#[derive(Debug, Clone)]
+struct Watched {
+    lesson_id: i64,
+    start_time: i64,
+    /// 下次查询的时间，毫秒时间戳。
+    next_poll: i64,
+    interval: Duration,
+}
+pub struct RecordingMonitor {
+    options: MonitorOptions,
+    watched: Vec<Watched>,
+}
+impl RecordingMonitor {
+    pub fn new(options: MonitorOptions) -> Self {
+        Self {
+            options,
+            watched: Vec::new(),
+        }
+    }
+    /// 正在监视的课次数。
+    pub fn len(&self) -> usize {
+        self.watched.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.watched.is_empty()
+    }
+    /// 监视一个课次，`start_time` 为其开始时间（毫秒时间戳）。重复添加的课次会被忽略。
+    pub fn watch(&mut self, lesson_id: i64, start_time: i64) {
+        self.watch_from(lesson_id, start_time, 0);
+    }
+    /// 监视一个课次，首次查询的时间为 `next_poll`（毫秒时间戳）。
+    fn watch_from(&mut self, lesson_id: i64, start_time: i64, next_poll: i64) {
+        if self.watched.iter().any(|w| w.lesson_id == lesson_id) {
+            return;
+        }
+        self.watched.push(Watched {
+            lesson_id,
+            start_time,
+            next_poll,
+            interval: self.options.initial_interval,
+        });
+    }
+    /// 监视课程中已开始、尚在放弃期限内且尚无录播的课次。
+    ///
+    /// 各课次会先查询一次，已有录播的课次不会被监视，也不会产生事件。
+    pub fn watch_course(
+        &mut self,
+        session: &impl SessionClient,
+        live_id: i64,
+    ) -> Result<(), Box<ureq::Error>> {
+        let now = Local::now().timestamp_millis();
+        let window = self.options.give_up_after.as_millis() as i64;
+        for lesson in Lesson::get_lessons(session, live_id)? {
+            let start_time = lesson.get_start_time();
+            if start_time > now || now - start_time >= window {
+                continue;
+            }
+            match crate::tools::get_recording_live_video_path(session, lesson.get_live_id()) {
+                Ok(video_path) if !video_path.is_default() => continue,
+                Ok(_) => {}
+                Err(e) => warn!("录播地址获取错误：{e}."),
+            }
+            let next_poll = now + self.options.initial_interval.as_millis() as i64;
+            self.watch_from(lesson.get_live_id(), start_time, next_poll);
+        }
+        Ok(())
+    }
+    /// 查询所有到期的课次，返回产生的事件。产生事件的课次不再被监视。
+    pub fn poll(&mut self, client: &impl Client) -> Vec<MonitorEvent> {
+        self.poll_at(Local::now().timestamp_millis(), |lesson_id| {
+            crate::tools::get_recording_live_video_path(client, lesson_id)
+        })
+    }
+    /// 以 `now`（毫秒时间戳）为当前时间，使用 `query` 查询所有到期的课次。
+    fn poll_at(
+        &mut self,
+        now: i64,
+        mut query: impl FnMut(i64) -> Result<VideoPath, Box<ureq::Error>>,
+    ) -> Vec<MonitorEvent> {
+        let window = self.options.give_up_after.as_millis() as i64;
+        let max_interval = self.options.max_interval;
+        let mut events = Vec::new();
+        self.watched.retain_mut(|w| {
+            if w.next_poll > now {
+                return true;
+            }
+            match query(w.lesson_id) {
+                Ok(video_path) if !video_path.is_default() => {
+                    events.push(MonitorEvent::Available {
+                        lesson_id: w.lesson_id,
+                        start_time: w.start_time,
+                        video_path,
+                    });
+                    return false;
+                }
+                Ok(_) => debug!("monitor: 课次 {} 暂无录播。", w.lesson_id),
+                Err(e) => warn!("录播地址获取错误：{e}."),
+            }
+            if now - w.start_time >= window {
+                events.push(MonitorEvent::GaveUp {
+                    lesson_id: w.lesson_id,
+                    start_time: w.start_time,
+                });
+                return false;
+            }
+            w.next_poll = now + w.interval.as_millis() as i64;
+            w.interval = (w.interval * 2).min(max_interval);
+            true
+        });
+        events
+    }
+    /// 距下次查询的时间，没有监视中的课次时返回 `None`.
+    pub fn next_poll_in(&self) -> Option<Duration> {
+        let now = Local::now().timestamp_millis();
+        self.watched
+            .iter()
+            .map(|w| w.next_poll)
+            .min()
+            .map(|t| Duration::from_millis((t - now).max(0) as u64))
+    }
+    /// 持续查询，直到所有课次都产生事件或 [`ProgressTracker::go_on`] 返回 `false`.
+    pub fn run<P: ProgressTracker + 'static>(
+        &mut self,
+        client: &impl Client,
+        mut on_event: impl FnMut(MonitorEvent),
+        multi: &impl ProgressTrackerHolder<P>,
+    ) {
+        let pb = multi.init(self.len() as u64, ProgressState::MonitorRecordings);
+        while let Some(wait) = self.next_poll_in() {
+            if !pb.go_on() {
+                debug!("monitor: break.");
+                break;
+            }
+            if !wait.is_zero() {
+                // 分段等待，以便及时响应取消。
+                std::thread::sleep(wait.min(Duration::from_secs(30)));
+                continue;
+            }
+            for event in self.poll(client) {
+                let gave_up = std::io::Error::from(std::io::ErrorKind::TimedOut);
+                let (lesson_id, result) = match &event {
+                    MonitorEvent::Available { lesson_id, .. } => (*lesson_id, Ok(())),
+                    MonitorEvent::GaveUp { lesson_id, .. } => (*lesson_id, Err(&gave_up as _)),
+                };
+                pb.on_item(result, &ProgressItem::Recording { lesson_id });
+                pb.inc(1);
+                on_event(event);
+            }
+        }
+        pb.finish(ProgressState::MonitorRecordings);
+        multi.remove_progress(&pb);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    fn monitor() -> RecordingMonitor {
+        let mut monitor = RecordingMonitor::new(MonitorOptions {
+            initial_interval: Duration::from_secs(60),
+            max_interval: Duration::from_secs(180),
+            give_up_after: Duration::from_secs(10 * 60),
+        });
+        monitor.watch(1, 0);
+        monitor
+    }
+    fn empty(_: i64) -> Result<VideoPath, Box<ureq::Error>> {
+        Ok(VideoPath::default())
+    }
+
+    #[test]
+    fn test_poll_backoff() {
+        let mut monitor = monitor();
+        let mut polls = Vec::new();
+        for now in (0..=9).map(|m| m * MINUTE) {
+            let events = monitor.poll_at(now, |id| {
+                polls.push(now);
+                empty(id)
+            });
+            assert!(events.is_empty());
+        }
+        // 间隔依次为 1, 2, 3, 3 分钟。
+        assert_eq!(polls, [0, MINUTE, 3 * MINUTE, 6 * MINUTE, 9 * MINUTE]);
+        let video_path: VideoPath =
+            serde_json::from_str(r#"{"ppt_video":"http://a/p.mp4"}"#).unwrap();
+        let events = monitor.poll_at(9 * MINUTE, |_| unreachable!());
+        assert!(events.is_empty());
+        let events = monitor.poll_at(12 * MINUTE, |_| Ok(video_path.clone()));
+        assert!(matches!(
+            events[..],
+            [MonitorEvent::Available { lesson_id: 1, .. }]
+        ));
+        assert!(monitor.is_empty());
+    }
+    #[test]
+    fn test_poll_give_up() {
+        let mut monitor = monitor();
+        assert!(monitor.poll_at(9 * MINUTE, empty).is_empty());
+        let events = monitor.poll_at(10 * MINUTE, |_| Err(Box::new(ureq::Error::StatusCode(500))));
+        assert!(matches!(
+            events[..],
+            [MonitorEvent::GaveUp {
+                lesson_id: 1,
+                start_time: 0
+            }]
+        ));
+        assert!(monitor.is_empty());
+        assert_eq!(monitor.next_poll_in(), None);
+    }
+}
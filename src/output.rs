@@ -0,0 +1,720 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 结果的输出格式。
+//!
+//! 除 JSON 与 TOML 外，结果还可以展平为表格，以 CSV、Markdown 表格或文本表格输出。展平规则如下：
+//! - 序列的每个元素、映射的每个键值对各占一行，映射的键位于 `key` 列。顶层的结构体视为映射，各字段各占一行；
+//! - 二元组视为键值对，因此 [`crate::PairVec`] 与 [`crate::map_sort_by_key`] 的结果与映射一致；
+//! - 行中的结构体与映射的各字段各占一列，按序列化的顺序排列，嵌套的字段以 `.` 连接，如 `1.device_code`;
+//! - 单独的值位于 `value` 列，`None` 为空。
+//!
+//! 写入文件时可使用 [`OutputSink`], 由 [`PathTemplate`] 根据课次、教室等信息生成路径。
+
+use crate::{lesson::Lesson, Live, Room, StreamKind};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    error::Error as ErrorTrait,
+    fmt::{Display, Formatter},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// 带缩进的 JSON.
+    #[default]
+    Json,
+    /// 每行一个 JSON 值，序列按元素、映射按键值对分行。
+    #[serde(alias = "jsonl")]
+    Ndjson,
+    Csv,
+    Toml,
+    /// Markdown 表格。
+    #[serde(alias = "md")]
+    Markdown,
+    /// 带边框的文本表格，适合在终端中查看。
+    Table,
+}
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 6] = [
+        OutputFormat::Json,
+        OutputFormat::Ndjson,
+        OutputFormat::Csv,
+        OutputFormat::Toml,
+        OutputFormat::Markdown,
+        OutputFormat::Table,
+    ];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Markdown => "markdown",
+            OutputFormat::Table => "table",
+        }
+    }
+    /// 根据文件扩展名推断格式，`.txt` 对应文本表格。
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "txt" => Some(OutputFormat::Table),
+            "md" => Some(OutputFormat::Markdown),
+            extension => extension.parse().ok(),
+        }
+    }
+}
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOutputFormatError(String);
+impl Display for ParseOutputFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "未知的输出格式：`{}`.", self.0)
+    }
+}
+impl ErrorTrait for ParseOutputFormatError {}
+impl FromStr for OutputFormat {
+    type Err = ParseOutputFormatError;
+
+    /// 另外接受 `jsonl` 与 `md` 两个别名。
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_lowercase();
+        match normalized.as_str() {
+            "jsonl" => Ok(OutputFormat::Ndjson),
+            "md" => Ok(OutputFormat::Markdown),
+            normalized => OutputFormat::ALL
+                .into_iter()
+                .find(|format| format.as_str() == normalized)
+                .ok_or_else(|| ParseOutputFormatError(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Toml(toml::ser::Error),
+    Csv(csv::Error),
+    /// 路径模板有误。
+    Template(String),
+    /// 该格式不能追加写入。
+    AppendUnsupported(OutputFormat),
+}
+impl Display for OutputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputError::Io(e) => write!(f, "写入内容出错：{e}"),
+            OutputError::Json(e) => write!(f, "序列化出错：{e}"),
+            OutputError::Toml(e) => write!(f, "TOML 序列化出错：{e}"),
+            OutputError::Csv(e) => write!(f, "CSV 序列化出错：{e}"),
+            OutputError::Template(e) => write!(f, "路径模板有误：{e}"),
+            OutputError::AppendUnsupported(format) => write!(f, "{format} 格式不支持追加写入"),
+        }
+    }
+}
+impl ErrorTrait for OutputError {}
+impl From<std::io::Error> for OutputError {
+    fn from(e: std::io::Error) -> Self {
+        OutputError::Io(e)
+    }
+}
+impl From<serde_json::Error> for OutputError {
+    fn from(e: serde_json::Error) -> Self {
+        OutputError::Json(e)
+    }
+}
+impl From<toml::ser::Error> for OutputError {
+    fn from(e: toml::ser::Error) -> Self {
+        OutputError::Toml(e)
+    }
+}
+impl From<csv::Error> for OutputError {
+    fn from(e: csv::Error) -> Self {
+        OutputError::Csv(e)
+    }
+}
+
+/// TOML 没有空值，`null` 会被略去。
+fn into_toml(value: Value) -> Option<toml::Value> {
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(b) => toml::Value::Boolean(b),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => toml::Value::Integer(i),
+            (None, Some(f)) if !n.is_u64() => toml::Value::Float(f),
+            _ => toml::Value::String(n.to_string()),
+        },
+        Value::String(s) => toml::Value::String(s),
+        Value::Array(items) => {
+            toml::Value::Array(items.into_iter().filter_map(into_toml).collect())
+        }
+        Value::Object(entries) => toml::Value::Table(
+            entries
+                .into_iter()
+                .filter_map(|(k, v)| Some((k, into_toml(v)?)))
+                .collect(),
+        ),
+    })
+}
+fn text(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s,
+        value => value.to_string(),
+    }
+}
+
+type Cells = Vec<(String, String)>;
+fn flatten(prefix: &str, value: Value, cells: &mut Cells) {
+    let join = |name: &str| {
+        if prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{prefix}.{name}")
+        }
+    };
+    match value {
+        Value::Object(entries) => {
+            for (name, value) in entries {
+                flatten(&join(&name), value, cells)
+            }
+        }
+        Value::Array(items) => {
+            for (i, value) in items.into_iter().enumerate() {
+                flatten(&join(&i.to_string()), value, cells)
+            }
+        }
+        value => {
+            let column = if prefix.is_empty() { "value" } else { prefix };
+            cells.push((column.to_owned(), text(value)))
+        }
+    }
+}
+fn keyed_row(key: String, value: Value) -> Cells {
+    let mut cells = vec![("key".to_owned(), key)];
+    flatten("", value, &mut cells);
+    cells
+}
+fn row(value: Value) -> Cells {
+    match value {
+        Value::Array(mut items) if items.len() == 2 && is_scalar(&items[0]) => {
+            let value = items.pop().unwrap_or_default();
+            let key = items.pop().unwrap_or_default();
+            keyed_row(text(key), value)
+        }
+        value => {
+            let mut cells = Vec::new();
+            flatten("", value, &mut cells);
+            cells
+        }
+    }
+}
+fn is_scalar(value: &Value) -> bool {
+    matches!(value, Value::Bool(_) | Value::Number(_) | Value::String(_))
+}
+
+/// 展平后的表格，规则见[模块文档](self)。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+/// 终端中的显示宽度，中日韩文字与全角符号计为 2.
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6 => 2,
+            _ => 1,
+        })
+        .sum()
+}
+impl Table {
+    pub fn new<S: Serialize + ?Sized>(contents: &S) -> Result<Table, serde_json::Error> {
+        let rows = match serde_json::to_value(contents)? {
+            Value::Array(items) => items.into_iter().map(row).collect(),
+            Value::Object(entries) => entries
+                .into_iter()
+                .map(|(key, value)| keyed_row(key, value))
+                .collect(),
+            value => vec![row(value)],
+        };
+        Ok(Table::from_cells(rows))
+    }
+    fn from_cells(rows: Vec<Cells>) -> Table {
+        let mut headers = Vec::new();
+        let mut columns = HashMap::new();
+        for (name, _) in rows.iter().flatten() {
+            if !columns.contains_key(name) {
+                columns.insert(name.clone(), headers.len());
+                headers.push(name.clone());
+            }
+        }
+        let rows = rows
+            .into_iter()
+            .map(|cells| {
+                let mut row = vec![String::new(); headers.len()];
+                for (name, value) in cells {
+                    row[columns[&name]] = value;
+                }
+                row
+            })
+            .collect();
+        Table { headers, rows }
+    }
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+    pub fn rows(&self) -> &[Vec<String>] {
+        &self.rows
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+    pub fn to_csv(&self) -> Result<String, OutputError> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        if !self.headers.is_empty() {
+            writer.write_record(&self.headers)?;
+        }
+        for row in &self.rows {
+            writer.write_record(row)?;
+        }
+        let csv = writer
+            .into_inner()
+            .map_err(|e| OutputError::Io(e.into_error()))?;
+        Ok(String::from_utf8_lossy(&csv).into_owned())
+    }
+    pub fn to_markdown(&self) -> String {
+        if self.headers.is_empty() {
+            return String::new();
+        }
+        let line = |cells: &[String]| {
+            let cells = cells
+                .iter()
+                .map(|c| c.replace('|', "\\|").replace(['\r', '\n'], " "))
+                .collect::<Vec<_>>();
+            format!("| {} |\n", cells.join(" | "))
+        };
+        let mut markdown = line(&self.headers);
+        markdown.push_str(&line(&vec!["---".to_owned(); self.headers.len()]));
+        for row in &self.rows {
+            markdown.push_str(&line(row));
+        }
+        markdown
+    }
+    /// 带边框的文本表格。
+    pub fn to_text(&self) -> String {
+        if self.headers.is_empty() {
+            return String::new();
+        }
+        let widths = (0..self.headers.len())
+            .map(|i| {
+                std::iter::once(&self.headers)
+                    .chain(&self.rows)
+                    .map(|row| display_width(&row[i]))
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        let border = widths
+            .iter()
+            .map(|w| "-".repeat(w + 2))
+            .collect::<Vec<_>>()
+            .join("+");
+        let border = format!("+{border}+\n");
+        let line = |cells: &[String]| {
+            let cells = cells
+                .iter()
+                .zip(&widths)
+                .map(|(c, w)| {
+                    let c = c.replace(['\r', '\n'], " ");
+                    let padding = w - display_width(&c);
+                    format!(" {c}{} ", " ".repeat(padding))
+                })
+                .collect::<Vec<_>>();
+            format!("|{}|\n", cells.join("|"))
+        };
+        let mut text = border.clone();
+        text.push_str(&line(&self.headers));
+        text.push_str(&border);
+        for row in &self.rows {
+            text.push_str(&line(row));
+        }
+        if !self.rows.is_empty() {
+            text.push_str(&border);
+        }
+        text
+    }
+}
+
+/// 以 `format` 格式序列化 `contents`, 非空的结果总以换行结尾。
+pub fn render<S: Serialize + ?Sized>(
+    contents: &S,
+    format: OutputFormat,
+) -> Result<String, OutputError> {
+    let mut rendered = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(contents)?,
+        OutputFormat::Ndjson => {
+            let values = match serde_json::to_value(contents)? {
+                Value::Array(items) => items,
+                Value::Object(entries) => entries
+                    .into_iter()
+                    .map(|(key, value)| serde_json::json!({ "key": key, "value": value }))
+                    .collect(),
+                value => vec![value],
+            };
+            let mut ndjson = String::new();
+            for value in values {
+                ndjson.push_str(&serde_json::to_string(&value)?);
+                ndjson.push('\n');
+            }
+            ndjson
+        }
+        OutputFormat::Toml => {
+            // TOML 文档的顶层必须是表。
+            let value = match into_toml(serde_json::to_value(contents)?) {
+                Some(toml::Value::Table(table)) => table,
+                Some(value) => toml::Table::from_iter([("items".to_owned(), value)]),
+                None => toml::Table::new(),
+            };
+            toml::to_string_pretty(&value)?
+        }
+        OutputFormat::Csv => Table::new(contents)?.to_csv()?,
+        OutputFormat::Markdown => Table::new(contents)?.to_markdown(),
+        OutputFormat::Table => Table::new(contents)?.to_text(),
+    };
+    if !rendered.is_empty() && !rendered.ends_with('\n') {
+        rendered.push('\n');
+    }
+    Ok(rendered)
+}
+
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// 先写入同目录下的临时文件再重命名，写入中途出错或进程退出时不会留下不完整的文件。
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let Some(file_name) = path.file_name() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("路径 `{}` 不是文件。", path.display()),
+        ));
+    };
+    let tmp = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = (|| {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// [`PathTemplate`] 中可用的变量。
+///
+/// 变量的值会作为单个路径分量使用，其中的 `/` 等字符会被替换为 `_`.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateVars {
+    vars: HashMap<String, String>,
+}
+impl TemplateVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set(mut self, name: impl Into<String>, value: impl Display) -> Self {
+        self.vars.insert(name.into(), value.to_string());
+        self
+    }
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+    /// 设置 `lesson_id`, `date`（如 `2024-09-10`）与 `time`（如 `1025`），日期与时间为本地时间。
+    pub fn lesson(self, lesson: &Lesson) -> Self {
+        let start = DateTime::from_timestamp_millis(lesson.get_start_time())
+            .unwrap_or_default()
+            .with_timezone(&Local);
+        self.set("lesson_id", lesson.get_live_id())
+            .set("date", start.format("%Y-%m-%d"))
+            .set("time", start.format("%H%M"))
+    }
+    /// 设置 `room` 与 `device_code`.
+    pub fn room(self, room: &Room) -> Self {
+        self.set("room", room.name())
+            .set("device_code", room.device_code())
+    }
+    /// 设置 `live_id`, `week_day` 与 `jie`, 尚未设置 `course` 时以上课地点作为 `course`.
+    pub fn live(self, live: &Live) -> Self {
+        let vars = self
+            .set("live_id", live.get_id())
+            .set("week_day", live.get_week_day())
+            .set("jie", live.get_jie());
+        if vars.get("course").is_some() {
+            vars
+        } else {
+            vars.set("course", live.place().trim())
+        }
+    }
+    /// 设置 `stream`, 如 `teacher_track`.
+    pub fn stream(self, kind: StreamKind) -> Self {
+        self.set("stream", kind)
+    }
+}
+/// 替换路径分量中不能出现在文件名中的字符。
+fn sanitize_component(value: &str) -> String {
+    let value = value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    match value.as_str() {
+        "" | "." | ".." => "_".to_owned(),
+        _ => value,
+    }
+}
+/// 输出路径模板，如 `{course}/{date}_{stream}.json`. `{{` 与 `}}` 表示字面的花括号。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    template: String,
+}
+impl PathTemplate {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+    pub fn render(&self, vars: &TemplateVars) -> Result<PathBuf, OutputError> {
+        let mut path = String::with_capacity(self.template.len());
+        let mut chars = self.template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    path.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    path.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(OutputError::Template(format!(
+                            "`{}` 中的花括号未闭合",
+                            self.template
+                        )));
+                    };
+                    let name = rest[..end].trim();
+                    let Some(value) = vars.get(name) else {
+                        return Err(OutputError::Template(format!("未知的变量 `{name}`")));
+                    };
+                    path.push_str(&sanitize_component(value));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(OutputError::Template(format!(
+                        "`{}` 中有多余的 `}}`",
+                        self.template
+                    )))
+                }
+                c => path.push(c),
+            }
+        }
+        Ok(PathBuf::from(path))
+    }
+}
+impl Display for PathTemplate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+/// 将结果写入由模板生成的路径，路径中的目录会被自动创建。
+///
+/// 默认以 [`write_atomic`] 整体替换目标文件；NDJSON 格式还可以改为追加写入。
+#[derive(Debug, Clone)]
+pub struct OutputSink {
+    template: PathTemplate,
+    format: OutputFormat,
+    append: bool,
+}
+impl OutputSink {
+    pub fn new(template: impl Into<String>, format: OutputFormat) -> Self {
+        Self {
+            template: PathTemplate::new(template),
+            format,
+            append: false,
+        }
+    }
+    /// 追加而非替换，仅适用于 [`OutputFormat::Ndjson`].
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+    pub fn template(&self) -> &PathTemplate {
+        &self.template
+    }
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+    /// 写入 `contents`, 返回实际写入的路径。
+    pub fn write<S: Serialize + ?Sized>(
+        &self,
+        contents: &S,
+        vars: &TemplateVars,
+    ) -> Result<PathBuf, OutputError> {
+        if self.append && self.format != OutputFormat::Ndjson {
+            return Err(OutputError::AppendUnsupported(self.format));
+        }
+        let path = self.template.render(vars)?;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let contents = render(contents, self.format)?;
+        if self.append {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+            // 整体一次写入，避免多个进程同时追加时内容交错。
+            file.write_all(contents.as_bytes())?;
+        } else {
+            write_atomic(&path, contents.as_bytes())?;
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PairVec, VideoPath};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_table_flatten() {
+        let video_path: VideoPath = serde_json::from_str(
+            r#"{"ppt_video":"a,b","teacher_full":null,"teacher_track":null,"student_full":"c"}"#,
+        )
+        .unwrap();
+        let table = Table::new(&PairVec::new(vec![(1, video_path.clone())])).unwrap();
+        assert_eq!(
+            table.headers(),
+            [
+                "key",
+                "ppt_video",
+                "teacher_full",
+                "teacher_track",
+                "student_full"
+            ]
+        );
+        assert_eq!(table.rows(), [["1", "a,b", "", "", "c"]]);
+        assert_eq!(Table::new(&vec![(1, video_path)]).unwrap(), table);
+        let csv = render(
+            &PairVec::new(vec![(1, VideoPath::default())]),
+            OutputFormat::Csv,
+        )
+        .unwrap();
+        assert_eq!(
+            csv,
+            "key,ppt_video,teacher_full,teacher_track,student_full\n1,,,,\n"
+        );
+        let rooms = BTreeMap::from([("A-101", "x|y"), ("B-203", "z")]);
+        let markdown = render(&rooms, OutputFormat::Markdown).unwrap();
+        assert_eq!(
+            markdown,
+            "| key | value |\n| --- | --- |\n| A-101 | x\\|y |\n| B-203 | z |\n"
+        );
+    }
+    #[test]
+    fn test_render_formats() {
+        let rooms = BTreeMap::from([("教室", "abc")]);
+        assert_eq!(
+            render(&rooms, OutputFormat::Ndjson).unwrap(),
+            "{\"key\":\"教室\",\"value\":\"abc\"}\n"
+        );
+        assert_eq!(
+            render(&vec![1, 2], OutputFormat::Toml).unwrap(),
+            "items = [\n    1,\n    2,\n]\n"
+        );
+        let text = render(&rooms, OutputFormat::Table).unwrap();
+        let widths = text.lines().map(display_width).collect::<Vec<_>>();
+        assert!(widths.iter().all(|w| *w == widths[0]));
+        assert_eq!("JSONL".parse(), Ok(OutputFormat::Ndjson));
+        assert_eq!(
+            OutputFormat::from_path(Path::new("a/b.md")),
+            Some(OutputFormat::Markdown)
+        );
+    }
+    #[test]
+    fn test_output_sink() {
+        let dir = std::env::temp_dir().join(format!("xddcc-output-{}", std::process::id()));
+        let room: Room = serde_json::from_str(
+            r#"{"schoolRoomName":"B/203","deviceCode":"abc","schoolRoomId":1,"id":2}"#,
+        )
+        .unwrap();
+        let vars = TemplateVars::new()
+            .set("course", "课程")
+            .room(&room)
+            .stream(StreamKind::PptVideo);
+        let template = format!("{}/{{course}}/{{room}}_{{stream}}.ndjson", dir.display());
+        let sink = OutputSink::new(&template, OutputFormat::Ndjson).append(true);
+        let path = sink.write(&[1, 2], &vars).unwrap();
+        sink.write(&[3], &vars).unwrap();
+        assert_eq!(path, dir.join("课程").join("B_203_ppt_video.ndjson"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\n2\n3\n");
+        let sink = OutputSink::new(&template, OutputFormat::Ndjson);
+        sink.write(&[4], &vars).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "4\n");
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(PathTemplate::new("{date}").render(&vars).is_err());
+        assert!(OutputSink::new("a.csv", OutputFormat::Csv)
+            .append(true)
+            .write(&[1], &vars)
+            .is_err());
+    }
+}
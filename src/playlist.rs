@@ -0,0 +1,297 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 供 mpv, VLC 等播放器使用的扩展 M3U 与 XSPF 播放列表。
+
+use crate::{
+    feed::{escape_xml, lesson_title},
+    StreamKind, VideoPath,
+};
+use std::{
+    fmt::{Display, Formatter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaylistFormat {
+    /// 扩展 M3U (`.m3u8`).
+    #[default]
+    M3u,
+    Xspf,
+}
+impl PlaylistFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+    /// 根据文件扩展名推断格式。
+    pub fn from_path(path: &Path) -> Option<PlaylistFormat> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+impl Display for PlaylistFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePlaylistFormatError(String);
+impl Display for ParsePlaylistFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "未知的播放列表格式：`{}`.", self.0)
+    }
+}
+impl std::error::Error for ParsePlaylistFormatError {}
+impl FromStr for PlaylistFormat {
+    type Err = ParsePlaylistFormatError;
+
+    /// 接受 `m3u`, `m3u8` 与 `xspf`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "m3u" | "m3u8" => Ok(PlaylistFormat::M3u),
+            "xspf" => Ok(PlaylistFormat::Xspf),
+            _ => Err(ParsePlaylistFormatError(s.to_owned())),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistEntry {
+    title: String,
+    url: String,
+    kind: Option<StreamKind>,
+}
+impl PlaylistEntry {
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn kind(&self) -> Option<StreamKind> {
+        self.kind
+    }
+}
+/// 播放列表，条目按添加的顺序排列。
+#[derive(Debug, Clone)]
+pub struct PlayerPlaylist {
+    title: String,
+    group_by_stream: bool,
+    entries: Vec<PlaylistEntry>,
+}
+impl PlayerPlaylist {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            group_by_stream: false,
+            entries: Vec::new(),
+        }
+    }
+    /// 按视频类型分组：同一类型的条目排在一起，并标注所属的组。
+    pub fn group_by_stream(mut self, group_by_stream: bool) -> Self {
+        self.group_by_stream = group_by_stream;
+        self
+    }
+    pub fn entries(&self) -> &[PlaylistEntry] {
+        &self.entries
+    }
+    pub fn add(
+        &mut self,
+        title: impl Into<String>,
+        url: impl Into<String>,
+        kind: Option<StreamKind>,
+    ) {
+        self.entries.push(PlaylistEntry {
+            title: title.into(),
+            url: url.into(),
+            kind,
+        });
+    }
+    /// 添加 `video_path` 中属于 `kinds` 的各路视频，见 [`VideoPath::titled_streams`].
+    ///
+    /// 返回添加的数目。
+    pub fn add_video_path(
+        &mut self,
+        name: &str,
+        video_path: &VideoPath,
+        kinds: &[StreamKind],
+    ) -> usize {
+        let count = self.entries.len();
+        for (kind, url, title) in video_path.titled_streams(name, kinds) {
+            self.add(title, url, Some(kind));
+        }
+        self.entries.len() - count
+    }
+    /// 按开始时间的顺序添加 [`crate::lesson::Lesson::get_recording_lives`] 的结果，标题为课程名加上课时间。
+    ///
+    /// 返回添加的数目。
+    pub fn add_recordings<'a>(
+        &mut self,
+        course: &str,
+        recordings: impl IntoIterator<Item = (&'a i64, &'a VideoPath)>,
+        kinds: &[StreamKind],
+    ) -> usize {
+        let mut recordings = recordings.into_iter().collect::<Vec<_>>();
+        recordings.sort_by_key(|(start_time, _)| **start_time);
+        recordings
+            .into_iter()
+            .map(|(start_time, video_path)| {
+                self.add_video_path(&lesson_title(course, *start_time), video_path, kinds)
+            })
+            .sum()
+    }
+    /// 实际输出的顺序，分组时同一类型的条目排在一起，组间按首次出现的顺序排列。
+    fn ordered(&self) -> Vec<&PlaylistEntry> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        if self.group_by_stream {
+            let mut groups = Vec::new();
+            for entry in &self.entries {
+                if !groups.contains(&entry.kind) {
+                    groups.push(entry.kind);
+                }
+            }
+            entries.sort_by_key(|entry| groups.iter().position(|kind| *kind == entry.kind));
+        }
+        entries
+    }
+    pub fn to_m3u(&self) -> String {
+        let mut m3u = String::from("#EXTM3U\n");
+        let _ = writeln!(m3u, "#PLAYLIST:{}", self.title.replace(['\r', '\n'], " "));
+        for entry in self.ordered() {
+            // 标题位于逗号之后，其中的换行会破坏格式。
+            let title = entry.title.replace(['\r', '\n'], " ");
+            match entry.kind.filter(|_| self.group_by_stream) {
+                Some(kind) => {
+                    let _ = writeln!(m3u, "#EXTINF:-1 group-title=\"{kind}\",{title}");
+                    let _ = writeln!(m3u, "#EXTGRP:{kind}");
+                }
+                None => {
+                    let _ = writeln!(m3u, "#EXTINF:-1,{title}");
+                }
+            }
+            let _ = writeln!(m3u, "{}", entry.url);
+        }
+        m3u
+    }
+    /// 分组时使用 VLC 的扩展，其它播放器会忽略分组。
+    pub fn to_xspf(&self) -> String {
+        const VLC: &str = "http://www.videolan.org/vlc/playlist/0";
+        let entries = self.ordered();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        if self.group_by_stream {
+            let _ = writeln!(
+                xml,
+                "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\" xmlns:vlc=\"{VLC}\">"
+            );
+        } else {
+            xml.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+        }
+        let _ = writeln!(xml, "<title>{}</title>", escape_xml(&self.title));
+        xml.push_str("<trackList>\n");
+        for (id, entry) in entries.iter().enumerate() {
+            xml.push_str("<track>\n");
+            let _ = writeln!(xml, "<location>{}</location>", escape_xml(&entry.url));
+            let _ = writeln!(xml, "<title>{}</title>", escape_xml(&entry.title));
+            if self.group_by_stream {
+                let _ = writeln!(
+                    xml,
+                    "<extension application=\"{VLC}\"><vlc:id>{id}</vlc:id></extension>"
+                );
+            }
+            xml.push_str("</track>\n");
+        }
+        xml.push_str("</trackList>\n");
+        if self.group_by_stream {
+            let _ = writeln!(xml, "<extension application=\"{VLC}\">");
+            let mut start = 0;
+            while start < entries.len() {
+                let kind = entries[start].kind;
+                let end = entries[start..]
+                    .iter()
+                    .position(|entry| entry.kind != kind)
+                    .map(|i| start + i)
+                    .unwrap_or(entries.len());
+                let title = kind.map(|kind| kind.as_str()).unwrap_or("其它");
+                let _ = writeln!(xml, "<vlc:node title=\"{title}\">");
+                for id in start..end {
+                    let _ = writeln!(xml, "<vlc:item tid=\"{id}\"/>");
+                }
+                xml.push_str("</vlc:node>\n");
+                start = end;
+            }
+            xml.push_str("</extension>\n");
+        }
+        xml.push_str("</playlist>\n");
+        xml
+    }
+    pub fn render(&self, format: PlaylistFormat) -> String {
+        match format {
+            PlaylistFormat::M3u => self.to_m3u(),
+            PlaylistFormat::Xspf => self.to_xspf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_player_playlist() {
+        let video_path: VideoPath = serde_json::from_str(
+            r#"{"ppt_video":"http://a/p.m3u8","teacher_full":null,"teacher_track":"http://a/t.m3u8?a&b","student_full":null}"#,
+        )
+        .unwrap();
+        let recordings = HashMap::from([
+            (1_700_000_000_000, video_path.clone()),
+            (1_600_000_000_000, video_path),
+        ]);
+        let kinds = [StreamKind::TeacherTrack, StreamKind::PptVideo];
+        let mut playlist = PlayerPlaylist::new("课程").group_by_stream(true);
+        assert_eq!(playlist.add_recordings("课程", &recordings, &kinds), 4);
+        let first = &playlist.entries()[0];
+        assert_eq!(
+            first.title(),
+            lesson_title("课程", 1_600_000_000_000) + " teacher_track"
+        );
+        let m3u = playlist.to_m3u();
+        let urls = m3u
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "http://a/t.m3u8?a&b",
+                "http://a/t.m3u8?a&b",
+                "http://a/p.m3u8",
+                "http://a/p.m3u8"
+            ]
+        );
+        assert!(m3u.contains("#EXTGRP:ppt_video\n"));
+        let xspf = playlist.to_xspf();
+        assert!(xspf.contains("<location>http://a/t.m3u8?a&amp;b</location>"));
+        assert!(xspf.contains(
+            "<vlc:node title=\"ppt_video\">\n<vlc:item tid=\"2\"/>\n<vlc:item tid=\"3\"/>"
+        ));
+        assert_eq!(
+            PlaylistFormat::from_path(Path::new("a.M3U8")),
+            Some(PlaylistFormat::M3u)
+        );
+    }
+}
@@ -13,21 +13,133 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::StreamKind;
+use std::{
+    error::Error as ErrorTrait,
+    fmt::{Display, Formatter},
+};
+
 pub trait ProgressTrackerHolder<P: ProgressTracker> {
     fn init(&self, total: u64, data: ProgressState) -> P;
+    /// 创建 `parent` 的子进度。
+    ///
+    /// 由多个阶段组成的操作（如 [`crate::Live::get_lives_now`]）会先创建总数为阶段数的父进度，
+    /// 每个阶段再创建子进度，阶段完成时父进度加一。默认实现与 [`ProgressTrackerHolder::init`] 相同，不体现层级关系。
+    fn init_child(&self, parent: &P, total: u64, data: ProgressState) -> P {
+        let _ = parent;
+        self.init(total, data)
+    }
     fn remove_progress(&self, progress: &P);
 }
+/// 有父进度时创建子进度，否则创建顶层进度。
+pub(crate) fn init_stage<P: ProgressTracker>(
+    multi: &impl ProgressTrackerHolder<P>,
+    parent: Option<&P>,
+    total: u64,
+    data: ProgressState,
+) -> P {
+    match parent {
+        Some(parent) => multi.init_child(parent, total, data),
+        None => multi.init(total, data),
+    }
+}
 pub trait ProgressTracker: Send + Sized {
     fn inc(&self, delta: u64);
     fn go_on(&self) -> bool {
         true
     }
+    /// 每完成一项时调用，`result` 为该项的结果。
+    ///
+    /// 与 [`ProgressTracker::inc`] 分开调用，默认不做任何事。
+    fn on_item(&self, result: Result<(), &dyn ErrorTrait>, item: &ProgressItem) {
+        let _ = (result, item);
+    }
     fn finish(&self, data: ProgressState);
 }
+/// 进度所属的阶段。
 #[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ProgressState {
-    GetRecordingLives,
-    GetLiveIds,
+    /// [`crate::Live::get_lives_now`], 包括 [`ProgressState::GetLiveIds`] 与 [`ProgressState::GetLiveUrls`] 两个阶段。
+    GetLivesNow,
+    /// [`crate::Room::get_all_rooms`], 包括 [`ProgressState::GetLiveIds`] 与 [`ProgressState::GetDeviceCodes`] 两个阶段。
+    GetAllRooms,
+    /// 获取课程各课次的录播地址。
+    GetRecordingLives {
+        live_id: i64,
+    },
+    /// 扫描 `sessions` 个会话的课表。
+    GetLiveIds {
+        sessions: usize,
+    },
     GetLiveUrls,
     GetDeviceCodes,
+    DownloadSegments {
+        kind: StreamKind,
+    },
+    RecordLive {
+        kind: StreamKind,
+    },
+    ScheduleLectures,
+    MonitorRecordings,
+}
+impl Display for ProgressState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgressState::GetLivesNow => f.write_str("获取当前直播"),
+            ProgressState::GetAllRooms => f.write_str("获取所有教室"),
+            ProgressState::GetRecordingLives { live_id } => {
+                write!(f, "获取课程 {live_id} 的录播地址")
+            }
+            ProgressState::GetLiveIds { sessions } => write!(f, "扫描 {sessions} 个账号的课表"),
+            ProgressState::GetLiveUrls => f.write_str("获取直播地址"),
+            ProgressState::GetDeviceCodes => f.write_str("获取教室设备码"),
+            ProgressState::DownloadSegments { kind } => write!(f, "下载 {kind} 视频分片"),
+            ProgressState::RecordLive { kind } => write!(f, "录制 {kind} 直播"),
+            ProgressState::ScheduleLectures => f.write_str("按课表录制直播"),
+            ProgressState::MonitorRecordings => f.write_str("等待录播发布"),
+        }
+    }
+}
+/// 进度中完成的一项。
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressItem {
+    /// 某会话某学期某周的课表。
+    Week {
+        uid: String,
+        term_year: i32,
+        term: i32,
+        week: i64,
+    },
+    /// 直播号对应的教室。
+    Room { live_id: i64 },
+    /// 教室的直播地址。
+    LiveUrl { live_id: i64 },
+    /// 课次的录播地址。
+    Lesson { lesson_id: i64, start_time: i64 },
+    /// 视频分片，`index` 为其在下载状态中的序号。
+    Segment { kind: StreamKind, index: usize },
+    /// 计划中的一节课。
+    Lecture { live_id: i64, place: String },
+    /// 监视中的课次。
+    Recording { lesson_id: i64 },
+}
+impl Display for ProgressItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgressItem::Week {
+                uid,
+                term_year,
+                term,
+                week,
+            } => write!(f, "用户 {uid} 的 {term_year}-{term} 学期第 {week} 周课表"),
+            ProgressItem::Room { live_id } => write!(f, "直播 {live_id} 的教室"),
+            ProgressItem::LiveUrl { live_id } => write!(f, "直播 {live_id} 的直播地址"),
+            ProgressItem::Lesson { lesson_id, .. } => write!(f, "课次 {lesson_id} 的录播地址"),
+            ProgressItem::Segment { kind, index } => write!(f, "{kind} 视频的第 {index} 个分片"),
+            ProgressItem::Lecture { live_id, place } => write!(f, "{place} 的课程（{live_id}）"),
+            ProgressItem::Recording { lesson_id } => write!(f, "课次 {lesson_id} 的录播"),
+        }
+    }
 }
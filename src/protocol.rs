@@ -13,44 +13,147 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use cxlib_types::Session;
-use ureq::{http::Response, Agent, Body};
+use crate::{Client, SessionClient};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter},
+    sync::OnceLock,
+};
+use ureq::{http::Response, Body, ResponseExt};
 
-static GET_VIEW_URL_HLS: &str = "http://newesxidian.chaoxing.com/live/getViewUrlHls";
-pub fn get_view_url_hls(agent: &Agent, live_id: i64) -> Result<Response<Body>, Box<ureq::Error>> {
-    let url = format!("{GET_VIEW_URL_HLS}?liveId={live_id}&status=2&jie=&isStudent=");
-    Ok(agent.get(&url).call()?)
+/// 录直播平台的地址与学校的机构号，默认为西电的平台。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Server {
+    host: String,
+    fid: i64,
 }
-static LIST_STUDENT_COURSE_LIVE_PAGE: &str =
-    "http://newesxidian.chaoxing.com/frontLive/listStudentCourseLivePage";
+impl Server {
+    pub fn new(host: impl Into<String>, fid: i64) -> Self {
+        Self {
+            host: host.into(),
+            fid,
+        }
+    }
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+    pub fn fid(&self) -> i64 {
+        self.fid
+    }
+}
+impl Default for Server {
+    fn default() -> Self {
+        Self::new("newesxidian.chaoxing.com", 16820)
+    }
+}
+/// 未指定平台的客户端使用的平台，见 [`crate::Client::server`].
+pub(crate) fn default_server() -> &'static Server {
+    static DEFAULT: OnceLock<Server> = OnceLock::new();
+    DEFAULT.get_or_init(Server::default)
+}
+
+static GET_VIEW_URL_HLS: &str = "/live/getViewUrlHls";
+pub fn get_view_url_hls(
+    client: &impl Client,
+    live_id: i64,
+) -> Result<Response<Body>, Box<ureq::Error>> {
+    let url = format!(
+        "http://{}{GET_VIEW_URL_HLS}?liveId={live_id}&status=2&jie=&isStudent=",
+        client.server().host
+    );
+    Ok(client.agent().get(&url).call()?)
+}
+/// 会话的请求被重定向到了平台以外的地址，以 [`ureq::Error::Other`] 返回。
+///
+/// 未登录或登录过期时平台会重定向到登录页，见 [`Redirected::is_login`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirected {
+    uri: String,
+}
+impl Redirected {
+    pub(crate) fn new(uri: impl Into<String>) -> Self {
+        Self { uri: uri.into() }
+    }
+    /// 重定向的目标地址。
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+    /// 目标是否为登录页，即会话未登录或已过期。
+    pub fn is_login(&self) -> bool {
+        let Ok(uri) = self.uri.parse::<ureq::http::Uri>() else {
+            return false;
+        };
+        uri.host().is_some_and(|host| host.starts_with("passport"))
+            || uri.path().to_ascii_lowercase().contains("login")
+    }
+}
+impl Display for Redirected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_login() {
+            write!(f, "请求被重定向到登录页 `{}`, 会话未登录或已过期", self.uri)
+        } else {
+            write!(f, "请求被重定向到 `{}`", self.uri)
+        }
+    }
+}
+impl std::error::Error for Redirected {}
+/// 以会话发起请求。被重定向到其它域名时返回 [`Redirected`].
+fn session_get(
+    session: &impl SessionClient,
+    url: &str,
+) -> Result<Response<Body>, Box<ureq::Error>> {
+    let resp = session.agent().get(url).call()?;
+    if resp.get_uri().host() != Some(session.server().host()) {
+        let redirected = Redirected::new(resp.get_uri().to_string());
+        return Err(Box::new(ureq::Error::Other(Box::new(redirected))));
+    }
+    Ok(resp)
+}
+static LIST_STUDENT_COURSE_LIVE_PAGE: &str = "/frontLive/listStudentCourseLivePage";
 pub fn list_student_course_live_page(
-    session: &Session,
+    session: &impl SessionClient,
     week: i64,
     term_year: i32,
     term: i32,
 ) -> Result<Response<Body>, Box<ureq::Error>> {
+    let server = session.server();
     let url = format!(
-        "{LIST_STUDENT_COURSE_LIVE_PAGE}?fid=16820&userId={}&week={week}&termYear={term_year}&termId={term}&type=1",
-        session.uid(),
+        "http://{}{LIST_STUDENT_COURSE_LIVE_PAGE}?fid={}&userId={}&week={week}&termYear={term_year}&termId={term}&type=1",
+        server.host,
+        server.fid,
+        session.session().uid(),
     );
-    Ok(session.get(&url).call()?)
+    session_get(session, &url)
 }
-static LIST_SINGLE_COURSE: &str = "http://newesxidian.chaoxing.com/live/listSignleCourse";
+static LIST_SINGLE_COURSE: &str = "/live/listSignleCourse";
 pub fn list_single_course(
-    session: &Session,
+    session: &impl SessionClient,
     live_id: i64,
 ) -> Result<Response<Body>, Box<ureq::Error>> {
+    let server = session.server();
     let url = format!(
-        "{LIST_SINGLE_COURSE}?fid=16820&liveId={live_id}&uId={}",
-        session.uid()
+        "http://{}{LIST_SINGLE_COURSE}?fid={}&liveId={live_id}&uId={}",
+        server.host,
+        server.fid,
+        session.session().uid()
     );
-    Ok(session.get(&url).call()?)
+    session_get(session, &url)
 }
 
-static GET_VIEW_URL: &str = "http://newesxidian.chaoxing.com/live/getViewUrlNoCourseLive";
-pub fn get_live_url(agent: &Agent, device_conde: &str) -> Result<Response<Body>, Box<ureq::Error>> {
-    let url = format!("{GET_VIEW_URL}?deviceCode={device_conde}&status=1&fid=16820");
-    Ok(agent.get(&url).call()?)
+static GET_VIEW_URL: &str = "/live/getViewUrlNoCourseLive";
+pub fn live_url(server: &Server, device_conde: &str) -> String {
+    format!(
+        "http://{}{GET_VIEW_URL}?deviceCode={device_conde}&status=1&fid={}",
+        server.host, server.fid
+    )
+}
+pub fn get_live_url(
+    client: &impl Client,
+    device_conde: &str,
+) -> Result<Response<Body>, Box<ureq::Error>> {
+    let url = live_url(client.server(), device_conde);
+    Ok(client.agent().get(&url).call()?)
 }
 // pub fn get_recording_url(
 //     agent: &Agent,
@@ -61,12 +164,15 @@ pub fn get_live_url(agent: &Agent, device_conde: &str) -> Result<Response<Body>,
 //     let url = format!("{GET_VIEW_URL}?deviceCode={device_conde}&status=2&fid=16820&startTime={start_time}&endTime={end_time}");
 //     agent.get(&url).call()
 // }
-static GET_WEEK_DETAIL: &str = "http://newesxidian.chaoxing.com/frontLive/getWeekDetail";
+static GET_WEEK_DETAIL: &str = "/frontLive/getWeekDetail";
 pub fn get_week_detail(
-    agent: &Agent,
+    client: &impl Client,
     week: i32,
     semester_id: i32,
 ) -> Result<Response<Body>, Box<ureq::Error>> {
-    let url = format!("{GET_WEEK_DETAIL}?week={week}&semesterId={semester_id}");
-    Ok(agent.get(&url).call()?)
+    let url = format!(
+        "http://{}{GET_WEEK_DETAIL}?week={week}&semesterId={semester_id}",
+        client.server().host
+    );
+    Ok(client.agent().get(&url).call()?)
 }
@@ -0,0 +1,279 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 直播的录制。
+//!
+//! 定时轮询直播的媒体播放列表并下载新出现的分片。分片与进度记录的格式与
+//! [`crate::download`] 相同，录制结束后可直接用 [`crate::ts::concat_download`] 拼接。
+
+use crate::{
+    download::{fetch_segment_with_retries, DownloadError, DownloadState, StateWriter},
+    hls::{MediaPlaylist, Playlist, Segment},
+    Client, ProgressItem, ProgressState, ProgressTracker, ProgressTrackerHolder, StreamKind,
+    Timetable, VideoPath,
+};
+use chrono::{DateTime, Local};
+use log::{debug, info, warn};
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct RecordOptions {
+    /// 到达该时间后停止录制。
+    pub stop_at: Option<DateTime<Local>>,
+    /// 轮询间隔，为 `None` 时取播放列表目标时长的一半。
+    pub poll_interval: Option<Duration>,
+    /// 持续该时长没有新分片（或播放列表无法获取）时，视为直播已结束。
+    pub idle_timeout: Duration,
+    /// 单个分片失败后的重试次数。
+    pub retries: u32,
+}
+impl Default for RecordOptions {
+    fn default() -> Self {
+        Self {
+            stop_at: None,
+            poll_interval: None,
+            idle_timeout: Duration::from_secs(5 * 60),
+            retries: 3,
+        }
+    }
+}
+impl RecordOptions {
+    /// 在今天从第 `jie` 节开始的大节下课时停止录制，下课时间取自 `timetable`.
+    pub fn until_jie_end(jie: i32, timetable: &Timetable) -> Self {
+        Self {
+            stop_at: timetable.end_today(jie),
+            ..Default::default()
+        }
+    }
+}
+/// 录制结束的原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordStop {
+    /// 到达 [`RecordOptions::stop_at`].
+    StopTime,
+    /// 播放列表中出现 `EXT-X-ENDLIST`.
+    EndList,
+    /// 超过 [`RecordOptions::idle_timeout`] 没有新分片。
+    Idle,
+    /// [`ProgressTracker::go_on`] 返回 `false`.
+    Cancelled,
+}
+impl Display for RecordStop {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordStop::StopTime => write!(f, "到达停止时间"),
+            RecordStop::EndList => write!(f, "直播已结束"),
+            RecordStop::Idle => write!(f, "长时间没有新分片"),
+            RecordStop::Cancelled => write!(f, "录制已取消"),
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub struct RecordReport {
+    pub stop: RecordStop,
+    /// 本次录制新下载的分片数。
+    pub segments: usize,
+    /// 本次录制新下载的分片总时长，单位为秒。
+    pub duration: f64,
+}
+
+/// 以地址判断分片是否已经处理过。
+///
+/// 播放列表重置后学校会重用分片的地址，因此重置时清空已处理的地址。媒体序列号或不连续序列号回退即为重置；
+/// 窗口滑动时不连续序列号会正常增加，不视为重置。
+#[derive(Debug, Default)]
+struct SegmentTracker {
+    seen: HashSet<String>,
+    /// 上一次的 (不连续序列号, 媒体序列号).
+    last: Option<(u64, u64)>,
+}
+impl SegmentTracker {
+    fn new(seen: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            seen: seen.into_iter().collect(),
+            last: None,
+        }
+    }
+    /// 返回播放列表是否已重置，以及其中尚未处理过的分片。返回的分片随即视为已处理。
+    fn update<'a>(&mut self, playlist: &'a MediaPlaylist) -> (bool, Vec<&'a Segment>) {
+        let current = (playlist.discontinuity_sequence(), playlist.media_sequence());
+        let reset = self
+            .last
+            .is_some_and(|(discontinuity, media)| current.0 < discontinuity || current.1 < media);
+        if reset {
+            self.seen.clear();
+        }
+        self.last = Some(current);
+        let new_segments = playlist
+            .segments()
+            .iter()
+            .filter(|segment| self.seen.insert(segment.uri().to_owned()))
+            .collect();
+        (reset, new_segments)
+    }
+}
+/// 录制 `video_path` 中 `kind` 对应的直播到 `dir`.
+///
+/// `dir` 中已有同一直播的进度记录时会接着录制，已下载的分片不会重复下载。
+/// 播放列表重置（序列号回退）时，重用的分片地址同样会被下载，其后的第一个新分片会被标记为不连续。
+pub fn record_live<P: ProgressTracker + 'static>(
+    client: &impl Client,
+    video_path: &VideoPath,
+    kind: StreamKind,
+    dir: &Path,
+    options: &RecordOptions,
+    multi: &impl ProgressTrackerHolder<P>,
+) -> Result<RecordReport, DownloadError> {
+    let playlist_url = video_path
+        .get(kind)
+        .ok_or(DownloadError::StreamNotFound(kind))?;
+    std::fs::create_dir_all(dir)?;
+    let state = DownloadState::load(dir)
+        .filter(|s| s.playlist_url() == playlist_url)
+        .unwrap_or_else(|| DownloadState::new(playlist_url, kind));
+    let mut tracker = SegmentTracker::new(state.segments().iter().map(|s| s.uri().to_owned()));
+    let mut counter = state.segments().len();
+    let mut state = StateWriter::new(state);
+    // 接续之前的录制时，两次录制之间必然不连续。
+    let mut pending_discontinuity = counter > 0;
+    let mut last_new_segment = Instant::now();
+    let mut segments = 0;
+    let mut duration = 0.0;
+    let pb = multi.init(0, ProgressState::RecordLive { kind });
+    let stop = loop {
+        if !pb.go_on() {
+            break RecordStop::Cancelled;
+        }
+        if options.stop_at.is_some_and(|t| Local::now() >= t) {
+            break RecordStop::StopTime;
+        }
+        if last_new_segment.elapsed() >= options.idle_timeout {
+            break RecordStop::Idle;
+        }
+        let playlist = match Playlist::fetch_media(client, playlist_url) {
+            Ok(playlist) => playlist,
+            Err(e) => {
+                warn!("直播播放列表获取失败：{e}.");
+                std::thread::sleep(options.poll_interval.unwrap_or(Duration::from_secs(5)));
+                continue;
+            }
+        };
+        let (reset, new_segments) = tracker.update(&playlist);
+        if reset {
+            debug!("record_live: 播放列表已重置。");
+            pending_discontinuity = true;
+        }
+        for segment in new_segments {
+            let file = format!("{counter:06}.ts");
+            let item = ProgressItem::Segment {
+                kind,
+                index: counter,
+            };
+            match fetch_segment_with_retries(
+                client,
+                segment.uri(),
+                &dir.join(&file),
+                options.retries,
+            ) {
+                Ok(()) => {
+                    let discontinuity = segment.discontinuity() || pending_discontinuity;
+                    pending_discontinuity = false;
+                    state.push_done(segment.uri(), file, segment.duration(), discontinuity, dir);
+                    counter += 1;
+                    segments += 1;
+                    duration += segment.duration();
+                    last_new_segment = Instant::now();
+                    pb.on_item(Ok(()), &item);
+                    pb.inc(1);
+                }
+                Err(e) => {
+                    // 跳过的分片造成的缺口同样需要标记为不连续。
+                    warn!("分片 `{}` 下载失败，已跳过：{e}.", segment.uri());
+                    pb.on_item(Err(&e), &item);
+                    pending_discontinuity = true;
+                }
+            }
+        }
+        if playlist.end_list() {
+            break RecordStop::EndList;
+        }
+        let interval = options
+            .poll_interval
+            .unwrap_or(Duration::from_secs(playlist.target_duration().max(2)) / 2);
+        std::thread::sleep(interval);
+    };
+    state.try_flush(dir);
+    info!(
+        "直播录制结束（{}），共 {} 个分片，{:.0} 秒。",
+        stop, segments, duration
+    );
+    pb.finish(ProgressState::RecordLive { kind });
+    multi.remove_progress(&pb);
+    Ok(RecordReport {
+        stop,
+        segments,
+        duration,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn media(text: &str) -> MediaPlaylist {
+        match Playlist::parse("http://a.b/live/index.m3u8", text).unwrap() {
+            Playlist::Media(media) => media,
+            Playlist::Master(_) => panic!(),
+        }
+    }
+    fn uris(segments: &[&Segment]) -> Vec<String> {
+        segments.iter().map(|s| s.uri().to_owned()).collect()
+    }
+
+    #[test]
+    fn test_segment_tracker() {
+        let mut tracker = SegmentTracker::new(["http://a.b/live/0.ts".to_owned()]);
+        let first =
+            media("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:10\n#EXTINF:4,\n0.ts\n#EXTINF:4,\n1.ts\n");
+        let (reset, new_segments) = tracker.update(&first);
+        assert!(!reset);
+        assert_eq!(uris(&new_segments), ["http://a.b/live/1.ts"]);
+        // 窗口滑动，不连续序列号增加不是重置。
+        let slid = media(
+            "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:11\n#EXT-X-DISCONTINUITY-SEQUENCE:1\n\
+            #EXTINF:4,\n1.ts\n#EXTINF:4,\n2.ts\n",
+        );
+        let (reset, new_segments) = tracker.update(&slid);
+        assert!(!reset);
+        assert_eq!(uris(&new_segments), ["http://a.b/live/2.ts"]);
+        // 重置后重用的地址同样是新分片。
+        let restarted =
+            media("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:0\n#EXTINF:4,\n0.ts\n#EXTINF:4,\n1.ts\n");
+        let (reset, new_segments) = tracker.update(&restarted);
+        assert!(reset);
+        assert_eq!(
+            uris(&new_segments),
+            ["http://a.b/live/0.ts", "http://a.b/live/1.ts"]
+        );
+        let (reset, new_segments) = tracker.update(&restarted);
+        assert!(!reset);
+        assert!(new_segments.is_empty());
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 本地 HLS 中继，以固定的地址重新提供教室直播与课次录播：
+//! - `/live/{device_code}/{stream}.m3u8`
+//! - `/recording/{lesson_id}/{stream}.m3u8`
+//!
+//! `stream` 为 [`StreamKind`] 的字符串形式，如 `ppt_video`.
+//! 请求时按需通过 [`crate::get_live_video_path`] 或 [`crate::get_recording_live_video_path`] 获取真实地址，
+//! 播放列表中的地址会被改写为指向中继自身的 `/r/...`, 分片则缓存在内存中。
+//! 学校的地址过期后中继会重新获取，因此播放器可以长期收藏上述地址。
+
+use crate::{
+    hls::resolve_uri,
+    protocol::Server,
+    server::{header, lock, serve_requests},
+    AnonymousClient, CancellationHandle, Client, StreamKind, VideoPath,
+};
+use log::{debug, warn};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    io::{Cursor, Read},
+    net::ToSocketAddrs,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct RelayOptions {
+    /// 真实地址的缓存时间，过期后重新获取。
+    pub resolve_ttl: Duration,
+    /// 分片缓存的容量，单位为字节。
+    pub cache_bytes: usize,
+    /// 处理请求的线程数。
+    pub workers: usize,
+    /// 获取真实地址时请求的平台。
+    pub server: Server,
+}
+impl Default for RelayOptions {
+    fn default() -> Self {
+        Self {
+            resolve_ttl: Duration::from_secs(30),
+            cache_bytes: 256 * 1024 * 1024,
+            workers: 16,
+            server: Server::default(),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Source {
+    Live(String),
+    Recording(i64),
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Route {
+    Stream(Source, StreamKind),
+    /// 由中继改写过的地址，`playlist` 表示该地址为播放列表。
+    Resource {
+        token: u64,
+        playlist: bool,
+    },
+}
+/// 中继的响应。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Arc<Vec<u8>>,
+}
+impl RelayResponse {
+    fn new(status: u16, content_type: &'static str, body: impl Into<Arc<Vec<u8>>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::new(
+            status,
+            "text/plain; charset=utf-8",
+            message.into().into_bytes(),
+        )
+    }
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+    pub fn content_type(&self) -> &str {
+        self.content_type
+    }
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+impl From<Box<ureq::Error>> for RelayResponse {
+    fn from(e: Box<ureq::Error>) -> Self {
+        RelayResponse::error(502, format!("请求出错：{e}"))
+    }
+}
+impl From<std::io::Error> for RelayResponse {
+    fn from(e: std::io::Error) -> Self {
+        RelayResponse::error(502, format!("读取出错：{e}"))
+    }
+}
+/// 共享的响应体，以免发送缓存的分片时复制。
+struct SharedBody(Arc<Vec<u8>>);
+impl AsRef<[u8]> for SharedBody {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+const PLAYLIST_TYPE: &str = "application/vnd.apple.mpegurl";
+
+/// 地址路径部分的扩展名。
+fn extension(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    name.rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .filter(|ext| ext.len() <= 5 && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+fn is_playlist(url: &str) -> bool {
+    extension(url).is_some_and(|ext| ext.eq_ignore_ascii_case("m3u8") || ext == "m3u")
+}
+fn token(url: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    hasher.finish()
+}
+/// 中继上 `url` 对应的路径。
+fn local_path(url: &str) -> String {
+    match extension(url) {
+        Some(ext) => format!("/r/{:016x}.{ext}", token(url)),
+        None => format!("/r/{:016x}", token(url)),
+    }
+}
+fn content_type(url: &str) -> &'static str {
+    match extension(url).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("ts") => "video/mp2t",
+        Some("aac") => "audio/aac",
+        Some("mp4" | "m4s") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+/// 将播放列表中的地址（包括标签中的 `URI` 属性）以 `base_url` 为基准解析，再交由 `rewrite` 改写。
+fn rewrite_playlist(
+    base_url: &str,
+    text: &str,
+    mut rewrite: impl FnMut(String) -> String,
+) -> String {
+    let mut playlist = String::with_capacity(text.len());
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('#') {
+            match line.find("URI=\"") {
+                Some(start) => {
+                    let start = start + 5;
+                    let end = line[start..]
+                        .find('"')
+                        .map(|i| start + i)
+                        .unwrap_or(line.len());
+                    playlist.push_str(&line[..start]);
+                    playlist.push_str(&rewrite(resolve_uri(base_url, &line[start..end])));
+                    playlist.push_str(&line[end..]);
+                }
+                None => playlist.push_str(line),
+            }
+        } else {
+            playlist.push_str(&rewrite(resolve_uri(base_url, line)));
+        }
+        playlist.push('\n');
+    }
+    playlist
+}
+fn parse_route(method: &str, url: &str) -> Result<Route, RelayResponse> {
+    if method != "GET" && method != "HEAD" {
+        return Err(RelayResponse::error(405, "只支持 GET 请求"));
+    }
+    let path = url.split('?').next().unwrap_or_default();
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let not_found = || RelayResponse::error(404, format!("未知的地址：`{path}`"));
+    let stream = |name: &str| {
+        name.strip_suffix(".m3u8")
+            .and_then(|kind| kind.parse::<StreamKind>().ok())
+            .ok_or_else(not_found)
+    };
+    match segments.as_slice() {
+        ["live", device_code, name] => Ok(Route::Stream(
+            Source::Live((*device_code).to_owned()),
+            stream(name)?,
+        )),
+        ["recording", lesson_id, name] => {
+            let lesson_id = lesson_id.parse().map_err(|_| not_found())?;
+            Ok(Route::Stream(Source::Recording(lesson_id), stream(name)?))
+        }
+        ["r", name] => {
+            let hex = name.split_once('.').map(|(hex, _)| hex).unwrap_or(name);
+            let token = u64::from_str_radix(hex, 16).map_err(|_| not_found())?;
+            Ok(Route::Resource {
+                token,
+                playlist: is_playlist(name),
+            })
+        }
+        _ => Err(not_found()),
+    }
+}
+/// 按插入顺序淘汰的分片缓存。
+#[derive(Default)]
+struct SegmentCache {
+    bytes: usize,
+    order: VecDeque<u64>,
+    entries: HashMap<u64, Arc<Vec<u8>>>,
+}
+impl SegmentCache {
+    fn get(&self, token: u64) -> Option<Arc<Vec<u8>>> {
+        self.entries.get(&token).cloned()
+    }
+    fn insert(&mut self, token: u64, data: Arc<Vec<u8>>, capacity: usize) {
+        if data.len() > capacity || self.entries.contains_key(&token) {
+            return;
+        }
+        while self.bytes + data.len() > capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(old) = self.entries.remove(&oldest) {
+                self.bytes -= old.len();
+            }
+        }
+        self.bytes += data.len();
+        self.order.push_back(token);
+        self.entries.insert(token, data);
+    }
+}
+/// 改写过的地址最多保留的时长，仅在记录较多时清理。
+const URL_RETENTION: Duration = Duration::from_secs(60 * 60);
+struct RelayInner {
+    options: RelayOptions,
+    client: AnonymousClient,
+    resolved: Mutex<HashMap<Source, (Instant, VideoPath)>>,
+    urls: Mutex<HashMap<u64, (Instant, String)>>,
+    segments: Mutex<SegmentCache>,
+}
+/// 本地 HLS 中继，见模块文档。
+///
+/// 克隆后的中继共享缓存。
+#[derive(Clone)]
+pub struct HlsRelay {
+    inner: Arc<RelayInner>,
+}
+impl Default for HlsRelay {
+    fn default() -> Self {
+        Self::new(RelayOptions::default())
+    }
+}
+impl HlsRelay {
+    pub fn new(options: RelayOptions) -> Self {
+        Self {
+            inner: Arc::new(RelayInner {
+                client: AnonymousClient::new().with_server(options.server.clone()),
+                options,
+                resolved: Mutex::new(HashMap::new()),
+                urls: Mutex::new(HashMap::new()),
+                segments: Mutex::new(SegmentCache::default()),
+            }),
+        }
+    }
+    fn video_path(&self, source: &Source) -> Result<VideoPath, RelayResponse> {
+        if let Some((at, video_path)) = lock(&self.inner.resolved).get(source) {
+            if at.elapsed() < self.inner.options.resolve_ttl {
+                return Ok(video_path.clone());
+            }
+        }
+        let client = &self.inner.client;
+        let video_path = match source {
+            Source::Live(device_code) => crate::get_live_video_path(client, device_code)?,
+            Source::Recording(lesson_id) => {
+                crate::get_recording_live_video_path(client, *lesson_id)?
+            }
+        };
+        lock(&self.inner.resolved).insert(source.clone(), (Instant::now(), video_path.clone()));
+        Ok(video_path)
+    }
+    /// 记录 `url`, 返回其在中继上的路径。
+    fn register(&self, url: String) -> String {
+        let path = local_path(&url);
+        let mut urls = lock(&self.inner.urls);
+        if urls.len() > 4096 {
+            urls.retain(|_, (at, _)| at.elapsed() < URL_RETENTION);
+        }
+        urls.insert(token(&url), (Instant::now(), url));
+        path
+    }
+    fn relay_playlist(&self, url: &str) -> Result<RelayResponse, RelayResponse> {
+        let text = self
+            .inner
+            .client
+            .agent()
+            .get(url)
+            .call()
+            .map_err(Box::new)?
+            .into_body()
+            .read_to_string()
+            .map_err(Box::new)?;
+        let playlist = rewrite_playlist(url, &text, |url| self.register(url));
+        Ok(RelayResponse::new(
+            200,
+            PLAYLIST_TYPE,
+            playlist.into_bytes(),
+        ))
+    }
+    fn relay_segment(&self, token: u64, url: &str) -> Result<RelayResponse, RelayResponse> {
+        let content_type = content_type(url);
+        if let Some(data) = lock(&self.inner.segments).get(token) {
+            debug!("分片 `{url}` 命中缓存。");
+            return Ok(RelayResponse::new(200, content_type, data));
+        }
+        let mut data = Vec::new();
+        self.inner
+            .client
+            .agent()
+            .get(url)
+            .call()
+            .map_err(Box::new)?
+            .into_body()
+            .into_reader()
+            .read_to_end(&mut data)?;
+        let data = Arc::new(data);
+        lock(&self.inner.segments).insert(token, Arc::clone(&data), self.inner.options.cache_bytes);
+        Ok(RelayResponse::new(200, content_type, data))
+    }
+    fn route(&self, route: Route) -> Result<RelayResponse, RelayResponse> {
+        match route {
+            Route::Stream(source, kind) => {
+                let video_path = self.video_path(&source)?;
+                let url = video_path
+                    .get(kind)
+                    .ok_or_else(|| RelayResponse::error(404, format!("没有 {kind} 视频")))?;
+                self.relay_playlist(url)
+            }
+            Route::Resource { token, playlist } => {
+                let url = lock(&self.inner.urls)
+                    .get(&token)
+                    .map(|(_, url)| url.clone())
+                    .ok_or_else(|| RelayResponse::error(404, "地址已过期，请重新获取播放列表"))?;
+                if playlist {
+                    self.relay_playlist(&url)
+                } else {
+                    self.relay_segment(token, &url)
+                }
+            }
+        }
+    }
+    /// 处理一个请求，便于嵌入其它 HTTP 服务。
+    pub fn respond(&self, method: &str, url: &str) -> RelayResponse {
+        match parse_route(method, url).and_then(|route| self.route(route)) {
+            Ok(response) | Err(response) => {
+                if response.status >= 500 {
+                    warn!(
+                        "中继 `{url}` 出错：{}.",
+                        String::from_utf8_lossy(&response.body)
+                    );
+                }
+                response
+            }
+        }
+    }
+    fn handle(&self, request: tiny_http::Request) {
+        let response = self.respond(request.method().as_str(), request.url());
+        let length = response.body.len();
+        // `HEAD` 请求的响应体由 `tiny_http` 省略。
+        let response = tiny_http::Response::new(
+            response.status.into(),
+            vec![
+                header("Content-Type", response.content_type),
+                header("Access-Control-Allow-Origin", "*"),
+            ],
+            Cursor::new(SharedBody(response.body)),
+            Some(length),
+            None,
+        );
+        if let Err(e) = request.respond(response) {
+            debug!("响应发送失败：{e}.");
+        }
+    }
+    /// 在 `addr` 上提供服务，直到 `cancel` 被取消。
+    pub fn serve(
+        &self,
+        addr: impl ToSocketAddrs,
+        cancel: &CancellationHandle,
+    ) -> std::io::Result<()> {
+        let relay = self.clone();
+        serve_requests(addr, self.inner.options.workers, cancel, move |request| {
+            relay.handle(request)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_playlist() {
+        let text = "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x1\n#EXTINF:2.0,\nseg/1.ts?t=1\n\n#EXT-X-ENDLIST\n";
+        let playlist = rewrite_playlist("http://a/live/index.m3u8", text, |url| local_path(&url));
+        let lines = playlist.lines().collect::<Vec<_>>();
+        let key = local_path("http://a/live/key.bin");
+        assert_eq!(
+            lines[1],
+            format!("#EXT-X-KEY:METHOD=AES-128,URI=\"{key}\",IV=0x1")
+        );
+        assert_eq!(lines[3], local_path("http://a/live/seg/1.ts?t=1"));
+        assert!(lines[3].ends_with(".ts"));
+        assert_eq!(lines[4], "#EXT-X-ENDLIST");
+        assert!(lines[3].starts_with("/r/"));
+        assert_eq!(
+            parse_route("GET", lines[3]),
+            Ok(Route::Resource {
+                token: token("http://a/live/seg/1.ts?t=1"),
+                playlist: false
+            })
+        );
+        assert_eq!(
+            parse_route("GET", "/recording/7/teacher_track.m3u8"),
+            Ok(Route::Stream(
+                Source::Recording(7),
+                StreamKind::TeacherTrack
+            ))
+        );
+        assert_eq!(
+            parse_route("GET", "/live/x/y.m3u8").unwrap_err().status,
+            404
+        );
+    }
+    #[test]
+    fn test_segment_cache() {
+        let mut cache = SegmentCache::default();
+        for token in 0..4 {
+            cache.insert(token, Arc::new(vec![0; 4]), 10);
+        }
+        assert_eq!(cache.bytes, 8);
+        assert!(cache.get(1).is_none());
+        assert!(cache.get(3).is_some());
+    }
+    #[test]
+    fn test_unresolved() {
+        // 没有服务监听的端口，请求立即失败。
+        let options = RelayOptions {
+            server: Server::new("127.0.0.1:1", 1),
+            ..Default::default()
+        };
+        let relay = HlsRelay::new(options);
+        let response = relay.respond("GET", "/live/x/ppt_video.m3u8");
+        assert_eq!(response.status(), 502);
+        assert_eq!(response.content_type(), "text/plain; charset=utf-8");
+        // 失败的结果不被缓存。
+        assert!(lock(&relay.inner.resolved).is_empty());
+        // 没有该路视频时返回 404.
+        lock(&relay.inner.resolved).insert(
+            Source::Live("y".to_owned()),
+            (Instant::now(), VideoPath::default()),
+        );
+        assert_eq!(relay.respond("GET", "/live/y/ppt_video.m3u8").status(), 404);
+    }
+    #[test]
+    fn test_expired_token() {
+        let relay = HlsRelay::default();
+        let path = local_path("http://a/live/seg/1.ts");
+        for method in ["GET", "HEAD"] {
+            let response = relay.respond(method, &path);
+            assert_eq!(response.status(), 404);
+            assert!(String::from_utf8_lossy(response.body()).contains("过期"));
+        }
+        assert_eq!(relay.respond("GET", "/r/not-hex.ts").status(), 404);
+    }
+}
@@ -16,16 +16,71 @@
 use crate::tools::{
     arc_into_inner_error_handler, json_parsing_error_handler, mutex_into_inner_error_handler,
 };
-use crate::{live::Live, tools::VideoPath, ProgressState, ProgressTracker, ProgressTrackerHolder};
+use crate::{
+    live::Live, progress::init_stage, tools::VideoPath, Client, ProgressItem, ProgressState,
+    ProgressTracker, ProgressTrackerHolder, SessionClient, SessionPool,
+};
 use chrono::{Datelike, Local};
-use cxlib_types::Session;
-use log::debug;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+/// 扫描全部教室时的参数。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ScanOptions {
+    /// 同时发出请求的线程数，由各会话平分。
+    pub threads: i32,
+    /// 扫描最近多少年的课表。
+    pub years: i32,
+    /// 所有线程合计每秒最多发出的请求数，为空时不限制。
+    pub rate_limit: Option<u32>,
+}
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            threads: 64,
+            years: 6,
+            rate_limit: None,
+        }
+    }
+}
+/// 在多个线程间共享的请求速率限制。
+struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+impl RateLimiter {
+    /// 每秒最多 `per_second` 次，为空或为 0 时不限制。
+    fn new(per_second: Option<u32>) -> Self {
+        Self {
+            interval: per_second
+                .filter(|n| *n > 0)
+                .map(|n| Duration::from_secs(1) / n),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+    /// 等待到下一个可以发出请求的时间。
+    fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let at = {
+            let mut next = self
+                .next
+                .lock()
+                .unwrap_or_else(mutex_into_inner_error_handler);
+            let at = (*next).max(Instant::now());
+            *next = at + interval;
+            at
+        };
+        std::thread::sleep(at.saturating_duration_since(Instant::now()));
+    }
+}
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Room {
     #[serde(rename = "schoolRoomName")]
@@ -48,8 +103,8 @@ impl Room {
         let _ = std::mem::replace(&mut self.name, name);
         self
     }
-    pub fn get_live_video_path(&self, session: &Session) -> Result<VideoPath, Box<ureq::Error>> {
-        crate::tools::get_live_video_path(session, &self.device_code)
+    pub fn get_live_video_path(&self, client: &impl Client) -> Result<VideoPath, Box<ureq::Error>> {
+        crate::tools::get_live_video_path(client, &self.device_code)
     }
 
     // pub fn get_live_video_path(&self, session: &Session) -> VideoPath {
@@ -58,7 +113,10 @@ impl Room {
     // pub fn get_live_url(&self, session: &Session) -> WebUrl {
     //     crate::tools::get_live_web_url(session, &self.device_code)
     // }
-    pub fn get_rooms(session: &Session, live_id: i64) -> Result<Option<Room>, Box<ureq::Error>> {
+    pub fn get_rooms(
+        session: &impl SessionClient,
+        live_id: i64,
+    ) -> Result<Option<Room>, Box<ureq::Error>> {
         let rooms: Vec<Room> = crate::protocol::list_single_course(session, live_id)?
             .into_body()
             .read_json()
@@ -68,39 +126,84 @@ impl Room {
             .find(|r| r.id == live_id)
             .map(|r| r.trim()))
     }
-    pub fn get_all_rooms<
-        'a,
-        Iter: Iterator<Item = &'a Session> + Clone,
-        P: ProgressTracker + 'static,
-    >(
-        mut sessions: Iter,
+    /// 获取所有教室名称及其设备码。
+    ///
+    /// 直播号的扫描只使用 `pool` 中可用的会话，设备码的查询则在可用的会话间轮转。
+    pub fn get_all_rooms<P: ProgressTracker + 'static>(
+        pool: &SessionPool,
+        multi: &impl ProgressTrackerHolder<P>,
+    ) -> HashMap<String, String> {
+        Room::get_all_rooms_with(pool, &ScanOptions::default(), multi)
+    }
+    /// 同 [`Room::get_all_rooms`], 但按 `options` 扫描。
+    pub fn get_all_rooms_with<P: ProgressTracker + 'static>(
+        pool: &SessionPool,
+        options: &ScanOptions,
         multi: &impl ProgressTrackerHolder<P>,
     ) -> HashMap<String, String> {
+        let operation = multi.init(2, ProgressState::GetAllRooms);
         let map = Arc::new(Mutex::new(HashMap::new()));
-        Room::get_all_live_id(
-            &sessions.clone().collect::<Vec<_>>(),
-            Arc::clone(&map),
+        let sessions = pool.usable_sessions();
+        if !sessions.is_empty() {
+            Room::scan_live_ids(
+                &sessions,
+                Arc::clone(&map),
+                options,
+                multi,
+                Some(&operation),
+            );
+        }
+        operation.inc(1);
+        let rooms = Arc::new(Mutex::new(HashMap::new()));
+        Room::query_rooms(
+            map.clone(),
+            pool.clone(),
+            rooms.clone(),
+            options.threads,
+            Arc::new(RateLimiter::new(options.rate_limit)),
             multi,
+            Some(&operation),
         );
-        let rooms = Arc::new(Mutex::new(HashMap::new()));
-        if let Some(session) = sessions.next() {
-            Room::id_to_rooms(map.clone(), (*session).clone(), rooms.clone(), multi);
-        }
+        operation.inc(1);
+        operation.finish(ProgressState::GetAllRooms);
+        multi.remove_progress(&operation);
         Arc::into_inner(rooms)
             .unwrap_or_else(arc_into_inner_error_handler)
             .into_inner()
             .unwrap_or_else(mutex_into_inner_error_handler)
     }
-    pub fn get_all_live_id<P: ProgressTracker + 'static>(
-        sessions: &[&Session],
+    pub fn get_all_live_id<S, P>(
+        sessions: &[&S],
         id_map: Arc<Mutex<HashMap<String, i64>>>,
         multi: &impl ProgressTrackerHolder<P>,
-    ) {
-        let now_year = Local::now().year();
-        let thread_count = 64 / sessions.len() as i32;
-        let week_total = 6 * 60;
+    ) where
+        S: SessionClient + Clone + Send + 'static,
+        P: ProgressTracker + 'static,
+    {
+        Room::scan_live_ids(sessions, id_map, &ScanOptions::default(), multi, None)
+    }
+    fn scan_live_ids<S, P>(
+        sessions: &[&S],
+        id_map: Arc<Mutex<HashMap<String, i64>>>,
+        options: &ScanOptions,
+        multi: &impl ProgressTrackerHolder<P>,
+        parent: Option<&P>,
+    ) where
+        S: SessionClient + Clone + Send + 'static,
+        P: ProgressTracker + 'static,
+    {
+        // 负数会使下方的总数溢出为极大的进度。
+        let years = options.years.max(0);
+        let first_year = Local::now().year() - years;
+        // 下方按 `thread_count - 1` 分块，至少需要两个线程。
+        let thread_count = (options.threads / sessions.len() as i32).max(2);
+        let week_total = years * 60;
+        let limiter = Arc::new(RateLimiter::new(options.rate_limit));
         let total = week_total * sessions.len() as i32;
-        let pb = multi.init(total as u64, ProgressState::GetLiveIds);
+        let state = ProgressState::GetLiveIds {
+            sessions: sessions.len(),
+        };
+        let pb = init_stage(multi, parent, total as u64, state.clone());
 
         let pb = Arc::new(Mutex::new(pb));
         let mut handles = Vec::new();
@@ -116,6 +219,7 @@ impl Room {
                 let session = (*session).clone();
                 let id_map = Arc::clone(&id_map);
                 let pb = Arc::clone(&pb);
+                let limiter = Arc::clone(&limiter);
                 let handle = std::thread::spawn(move || {
                     for date_count in i * week_thread..if i != thread_count - 1 {
                         (i + 1) * week_thread
@@ -126,11 +230,24 @@ impl Room {
                             debug!("list_rooms/get_all_live_id: break.");
                             break;
                         }
-                        let (year, term, week) =
-                            crate::tools::date_count_to_year_term_week(now_year, date_count);
-                        let lives = Live::get_lives(&session, week, year, term).unwrap_or_default();
-                        for live in lives {
-                            id_map.lock().unwrap().insert(live.0, live.1);
+                        let (year, term, week) = crate::tools::date_count_to_year_term_week_since(
+                            first_year, date_count,
+                        );
+                        let item = ProgressItem::Week {
+                            uid: session.session().uid().to_owned(),
+                            term_year: year,
+                            term,
+                            week,
+                        };
+                        limiter.wait();
+                        match Live::get_lives(&session, week, year, term) {
+                            Ok(lives) => {
+                                for live in lives {
+                                    id_map.lock().unwrap().insert(live.0, live.1);
+                                }
+                                pb.lock().unwrap().on_item(Ok(()), &item);
+                            }
+                            Err(e) => pb.lock().unwrap().on_item(Err(&*e), &item),
                         }
                         pb.lock().unwrap().inc(1)
                     }
@@ -145,21 +262,39 @@ impl Room {
             .unwrap_or_else(arc_into_inner_error_handler)
             .into_inner()
             .unwrap_or_else(mutex_into_inner_error_handler);
-        pb.finish(ProgressState::GetLiveIds);
+        pb.finish(state);
         multi.remove_progress(&pb);
     }
     pub fn id_to_rooms<P: ProgressTracker + 'static>(
         id_map: Arc<Mutex<HashMap<String, i64>>>,
-        session: Session,
+        pool: SessionPool,
         rooms: Arc<Mutex<HashMap<String, String>>>,
         pb_holder: &impl ProgressTrackerHolder<P>,
+    ) {
+        let threads = ScanOptions::default().threads;
+        let limiter = Arc::new(RateLimiter::new(None));
+        Room::query_rooms(id_map, pool, rooms, threads, limiter, pb_holder, None)
+    }
+    fn query_rooms<P: ProgressTracker + 'static>(
+        id_map: Arc<Mutex<HashMap<String, i64>>>,
+        pool: SessionPool,
+        rooms: Arc<Mutex<HashMap<String, String>>>,
+        thread_count: i32,
+        limiter: Arc<RateLimiter>,
+        pb_holder: &impl ProgressTrackerHolder<P>,
+        parent: Option<&P>,
     ) {
         let ids = id_map.lock().unwrap().values().copied().collect::<Vec<_>>();
         let len = ids.len() as i32;
         let total = len;
-        let pb = pb_holder.init(total as u64, ProgressState::GetDeviceCodes);
+        let pb = init_stage(
+            pb_holder,
+            parent,
+            total as u64,
+            ProgressState::GetDeviceCodes,
+        );
         let pb = Arc::new(Mutex::new(pb));
-        let thread_count = 64;
+        let thread_count = thread_count.max(1);
         let chunk_rest = len % thread_count;
         let chunk_count = len / thread_count + if chunk_rest == 0 { 0 } else { 1 };
         for i in 0..chunk_count {
@@ -175,17 +310,28 @@ impl Room {
             } as usize];
             for id in ids {
                 let id = *id;
-                let session = session.clone();
+                let pool = pool.clone();
                 let rooms = rooms.clone();
                 let pb = Arc::clone(&pb);
+                let limiter = Arc::clone(&limiter);
                 let handle = std::thread::spawn(move || {
                     if !pb.lock().unwrap().go_on() {
                         debug!("list_rooms/id_to_rooms: break.");
                         return;
                     }
-                    let room = Room::get_rooms(&session, id).unwrap();
-                    if let Some(room) = room {
-                        rooms.lock().unwrap().insert(room.name, room.device_code);
+                    limiter.wait();
+                    let item = ProgressItem::Room { live_id: id };
+                    match pool.with_failover(|session| Room::get_rooms(session, id)) {
+                        Ok(room) => {
+                            if let Some(room) = room {
+                                rooms.lock().unwrap().insert(room.name, room.device_code);
+                            }
+                            pb.lock().unwrap().on_item(Ok(()), &item);
+                        }
+                        Err(e) => {
+                            warn!("教室获取错误：{e}.");
+                            pb.lock().unwrap().on_item(Err(&*e), &item);
+                        }
                     }
                     pb.lock().unwrap().inc(1);
                 });
@@ -0,0 +1,515 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 按课表自动录制直播。
+//!
+//! 根据本周的课表生成录制计划并保存到文件，在每节课上课时开始录制、下课时停止。
+//! 计划文件记录了每节课的录制状态，重启后已录制的课程会被跳过。
+
+use crate::{
+    record::{record_live, RecordOptions, RecordStop},
+    Live, ProgressItem, ProgressState, ProgressTracker, ProgressTrackerHolder, Room, SessionPool,
+    StreamKind, Timetable,
+};
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LectureStatus {
+    Pending,
+    Recording,
+    Recorded,
+    Failed,
+}
+/// 计划中的一节课。
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScheduledLecture {
+    live_id: i64,
+    place: String,
+    week_day: u32,
+    jie: i32,
+    /// 上课时间，毫秒时间戳。
+    start: i64,
+    /// 下课时间，毫秒时间戳。
+    end: i64,
+    /// 有这节课的会话。
+    uids: Vec<String>,
+    status: LectureStatus,
+}
+impl ScheduledLecture {
+    pub fn live_id(&self) -> i64 {
+        self.live_id
+    }
+    pub fn place(&self) -> &str {
+        &self.place
+    }
+    pub fn week_day(&self) -> u32 {
+        self.week_day
+    }
+    pub fn jie(&self) -> i32 {
+        self.jie
+    }
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+    pub fn end(&self) -> i64 {
+        self.end
+    }
+    pub fn uids(&self) -> &[String] {
+        &self.uids
+    }
+    pub fn status(&self) -> LectureStatus {
+        self.status
+    }
+    /// 该课程各路视频的录制目录。
+    pub fn dir(&self, root: &Path, kind: StreamKind) -> PathBuf {
+        root.join(self.live_id.to_string()).join(kind.as_str())
+    }
+}
+/// 某一学期某一周的录制计划。
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SchedulePlan {
+    term_year: i32,
+    term: i32,
+    week: i64,
+    lectures: Vec<ScheduledLecture>,
+}
+fn monday_of(date: NaiveDate) -> NaiveDate {
+    date - Days::new(date.weekday().num_days_from_monday() as u64)
+}
+fn date_time_millis(date: NaiveDate, time: chrono::NaiveTime) -> Option<i64> {
+    Some(
+        date.and_time(time)
+            .and_local_timezone(Local)
+            .single()?
+            .timestamp_millis(),
+    )
+}
+impl SchedulePlan {
+    pub fn term_year(&self) -> i32 {
+        self.term_year
+    }
+    pub fn term(&self) -> i32 {
+        self.term
+    }
+    pub fn week(&self) -> i64 {
+        self.week
+    }
+    pub fn lectures(&self) -> &[ScheduledLecture] {
+        &self.lectures
+    }
+    /// 根据 `pool` 中可用会话本周的课表生成计划，同一节课只录制一次。上下课时间取自 `timetable`.
+    pub fn build(
+        pool: &SessionPool,
+        term_year: i32,
+        term: i32,
+        week: i64,
+        timetable: &Timetable,
+    ) -> SchedulePlan {
+        let mut lives = Vec::new();
+        for session in pool.usable_sessions() {
+            match Live::get_week_lives(session, week, term_year, term) {
+                Ok(week_lives) => lives.extend(week_lives.into_iter().map(|l| (session.uid(), l))),
+                Err(e) => {
+                    warn!("课表获取错误：{e}.");
+                    if crate::is_session_error(&e) {
+                        pool.mark_unhealthy(session);
+                    }
+                }
+            }
+        }
+        let monday = monday_of(Local::now().date_naive());
+        SchedulePlan::from_lives(lives, monday, timetable, term_year, term, week)
+    }
+    /// 由各会话（以 `uid` 标识）的课表生成计划，`monday` 为本周周一。
+    fn from_lives<'a>(
+        lives: impl IntoIterator<Item = (&'a str, Live)>,
+        monday: NaiveDate,
+        timetable: &Timetable,
+        term_year: i32,
+        term: i32,
+        week: i64,
+    ) -> SchedulePlan {
+        let mut lectures: Vec<ScheduledLecture> = Vec::new();
+        for (uid, live) in lives {
+            if let Some(lecture) = lectures.iter_mut().find(|l| l.live_id == live.get_id()) {
+                if !lecture.uids.iter().any(|u| u == uid) {
+                    lecture.uids.push(uid.to_owned());
+                }
+                continue;
+            }
+            let Some((start, end)) = timetable.range(live.get_jie()) else {
+                warn!("未知的节次：{}.", live.get_jie());
+                continue;
+            };
+            let Some(date) = live
+                .get_week_day()
+                .checked_sub(1)
+                .and_then(|d| monday.checked_add_days(Days::new(d as u64)))
+            else {
+                continue;
+            };
+            let (Some(start), Some(end)) =
+                (date_time_millis(date, start), date_time_millis(date, end))
+            else {
+                continue;
+            };
+            lectures.push(ScheduledLecture {
+                live_id: live.get_id(),
+                place: live.place().trim().to_owned(),
+                week_day: live.get_week_day(),
+                jie: live.get_jie(),
+                start,
+                end,
+                uids: vec![uid.to_owned()],
+                status: LectureStatus::Pending,
+            });
+        }
+        lectures.sort_by_key(|l| l.start);
+        SchedulePlan {
+            term_year,
+            term,
+            week,
+            lectures,
+        }
+    }
+    pub fn load(path: &Path) -> Option<SchedulePlan> {
+        let content = std::fs::read(path).ok()?;
+        match serde_json::from_slice(&content) {
+            Ok(plan) => Some(plan),
+            Err(e) => {
+                warn!("录制计划解析出错，将重新生成：{e}.");
+                None
+            }
+        }
+    }
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        crate::output::write_atomic(path, &serde_json::to_vec_pretty(self)?)
+    }
+    fn is_for(&self, term_year: i32, term: i32, week: i64) -> bool {
+        self.term_year == term_year && self.term == term && self.week == week
+    }
+    /// 沿用旧计划中相同课程的录制状态。
+    fn inherit(&mut self, old: &SchedulePlan) {
+        for lecture in &mut self.lectures {
+            if let Some(o) = old
+                .lectures
+                .iter()
+                .find(|o| o.live_id == lecture.live_id && o.start == lecture.start)
+            {
+                lecture.status = o.status;
+            }
+        }
+    }
+    /// 中断时正在录制的课程重新录制。
+    fn reset_interrupted(&mut self) {
+        for lecture in &mut self.lectures {
+            if lecture.status == LectureStatus::Recording {
+                lecture.status = LectureStatus::Pending;
+            }
+        }
+    }
+    /// 调度循环在 `now` 时的下一步：本周已无待录制的课程时等到下周一，否则等到最早的一节课开始。
+    fn next_wake(&self, now: DateTime<Local>) -> Wake {
+        let millis = now.timestamp_millis();
+        match self.next_pending(millis) {
+            Some(index) if self.lectures[index].start <= millis => Wake::Record(index),
+            Some(index) => Wake::Sleep(self.lectures[index].start),
+            None => {
+                let next_monday = monday_of(now.date_naive()) + Days::new(7);
+                Wake::Sleep(
+                    date_time_millis(next_monday, chrono::NaiveTime::MIN)
+                        .unwrap_or(millis + 24 * 3600 * 1000),
+                )
+            }
+        }
+    }
+    /// 下课时间在 `now` 之后且尚未录制的、最早开始的一节课。
+    fn next_pending(&self, now: i64) -> Option<usize> {
+        self.lectures
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.status == LectureStatus::Pending && l.end > now)
+            .min_by_key(|(_, l)| l.start)
+            .map(|(i, _)| i)
+    }
+}
+
+/// 调度循环的下一步。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wake {
+    /// 立即录制第 `index` 节课。
+    Record(usize),
+    /// 等待到该时间（毫秒时间戳）后重新检查。
+    Sleep(i64),
+}
+/// 学期信息获取失败后重试的间隔。
+const TERM_RETRY_MILLIS: i64 = 60 * 1000;
+/// 等待到 `until`（毫秒时间戳），期间 `go_on` 返回 `false` 时提前返回。
+fn sleep_until(until: i64, pb: &impl ProgressTracker) {
+    loop {
+        let remaining = until - Local::now().timestamp_millis();
+        if remaining <= 0 || !pb.go_on() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(remaining.min(30_000) as u64));
+    }
+}
+
+/// [`run_scheduler`] 的参数。
+#[derive(Debug, Clone)]
+pub struct SchedulerOptions {
+    /// 录制计划的保存路径。
+    pub plan_path: PathBuf,
+    /// 录制结果的保存目录，其中的路径见 [`ScheduledLecture::dir`].
+    pub dir: PathBuf,
+    /// 录制的视频。
+    pub kinds: Vec<StreamKind>,
+    /// 其中的 `stop_at` 会被各节课的下课时间覆盖。
+    pub record: RecordOptions,
+    /// 上下课时间。
+    pub timetable: Timetable,
+}
+impl SchedulerOptions {
+    /// 录制 [`StreamKind::PptVideo`], 其余参数取默认值。
+    pub fn new(plan_path: impl Into<PathBuf>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            plan_path: plan_path.into(),
+            dir: dir.into(),
+            kinds: vec![StreamKind::PptVideo],
+            record: RecordOptions::default(),
+            timetable: Timetable::default(),
+        }
+    }
+}
+/// 按课表持续录制 `pool` 中各会话的直播，直到 [`ProgressTracker::go_on`] 返回 `false`.
+///
+/// 每次唤醒时都会重新确认当前的学期与周次，跨周或跨学期时重新生成计划。
+/// 学期信息获取失败时稍后重试。
+pub fn run_scheduler<P, H>(
+    pool: &SessionPool,
+    options: &SchedulerOptions,
+    multi: &H,
+) -> std::io::Result<()>
+where
+    P: ProgressTracker + 'static,
+    H: ProgressTrackerHolder<P> + Sync,
+{
+    let SchedulerOptions {
+        plan_path,
+        dir,
+        kinds,
+        record,
+        timetable,
+    } = options;
+    let pb = multi.init(0, ProgressState::ScheduleLectures);
+    let mut plan = SchedulePlan::load(plan_path).map(|mut plan| {
+        plan.reset_interrupted();
+        plan
+    });
+    let client = pool.anonymous_client();
+    while pb.go_on() {
+        if pool.next().is_none() {
+            warn!("没有可用的会话，停止自动录制。");
+            break;
+        }
+        let (term_year, term, week) = match crate::tools::term_year_detail(&client) {
+            Ok(detail) => detail,
+            Err(e) => {
+                warn!("{e}, 稍后重试。");
+                sleep_until(Local::now().timestamp_millis() + TERM_RETRY_MILLIS, &pb);
+                continue;
+            }
+        };
+        let current = match plan.take() {
+            Some(p) if p.is_for(term_year, term, week) => p,
+            old => {
+                let mut new = SchedulePlan::build(pool, term_year, term, week, timetable);
+                if let Some(old) = old {
+                    new.inherit(&old);
+                }
+                info!(
+                    "生成录制计划：{term_year} 学年第 {term} 学期第 {week} 周，共 {} 节课。",
+                    new.lectures.len()
+                );
+                new.save(plan_path)?;
+                new
+            }
+        };
+        let current = plan.insert(current);
+        let index = match current.next_wake(Local::now()) {
+            Wake::Record(index) => index,
+            Wake::Sleep(until) => {
+                debug!("run_scheduler: 等待至 {until}.");
+                sleep_until(until, &pb);
+                continue;
+            }
+        };
+        let lecture = current.lectures[index].clone();
+        current.lectures[index].status = LectureStatus::Recording;
+        current.save(plan_path)?;
+        let video_path = pool
+            .with_failover(|session| Room::get_rooms(session, lecture.live_id))
+            .map(|room| room.map(|room| room.get_live_video_path(&client)));
+        let video_path = match video_path {
+            Ok(Some(Ok(video_path))) => video_path,
+            Ok(None) => {
+                warn!("没有找到直播 {} 对应的教室。", lecture.live_id);
+                current.lectures[index].status = LectureStatus::Failed;
+                current.save(plan_path)?;
+                continue;
+            }
+            Ok(Some(Err(e))) | Err(e) => {
+                warn!("直播地址获取错误：{e}.");
+                current.lectures[index].status = LectureStatus::Failed;
+                current.save(plan_path)?;
+                continue;
+            }
+        };
+        info!("开始录制 {} 的课程。", lecture.place);
+        let options = RecordOptions {
+            stop_at: chrono::DateTime::from_timestamp_millis(lecture.end)
+                .map(|t| t.with_timezone(&Local)),
+            ..record.clone()
+        };
+        let results = std::thread::scope(|scope| {
+            let handles = kinds
+                .iter()
+                .filter(|kind| video_path.get(**kind).is_some())
+                .map(|kind| {
+                    let (client, video_path, options) = (&client, &video_path, &options);
+                    let dir = lecture.dir(dir, *kind);
+                    scope
+                        .spawn(move || record_live(client, video_path, *kind, &dir, options, multi))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let mut cancelled = false;
+        let mut recorded = false;
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok(report) => {
+                    cancelled |= report.stop == RecordStop::Cancelled;
+                    recorded |= report.segments > 0;
+                }
+                Err(e) => {
+                    warn!("直播录制错误：{e}.");
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        let item = ProgressItem::Lecture {
+            live_id: lecture.live_id,
+            place: lecture.place.clone(),
+        };
+        match &error {
+            Some(e) if !recorded => pb.on_item(Err(e), &item),
+            _ => pb.on_item(Ok(()), &item),
+        }
+        current.lectures[index].status = if cancelled {
+            LectureStatus::Pending
+        } else if recorded {
+            LectureStatus::Recorded
+        } else {
+            LectureStatus::Failed
+        };
+        current.save(plan_path)?;
+        pb.inc(1);
+    }
+    pb.finish(ProgressState::ScheduleLectures);
+    multi.remove_progress(&pb);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::Section;
+    use chrono::{NaiveTime, TimeZone};
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+    fn plan() -> SchedulePlan {
+        let lives: Vec<Live> = serde_json::from_str(
+            r#"[{"place":" B-203 ","id":1,"weekDay":3,"jie":1},
+                {"place":"C-101","id":2,"weekDay":1,"jie":4},
+                {"place":"C-101","id":3,"weekDay":2,"jie":9},
+                {"place":"D-1","id":4,"weekDay":0,"jie":1}]"#,
+        )
+        .unwrap();
+        let shared = lives[0].clone();
+        let timetable = Timetable::new(vec![
+            Section::new(1, time(8, 0), time(9, 30)),
+            Section::new(3, time(10, 0), time(11, 30)),
+        ]);
+        let monday = NaiveDate::from_ymd_opt(2024, 9, 9).unwrap();
+        let lives = lives
+            .into_iter()
+            .map(|l| ("a", l))
+            .chain([("b", shared.clone()), ("b", shared)]);
+        SchedulePlan::from_lives(lives, monday, &timetable, 2024, 1, 1)
+    }
+
+    #[test]
+    fn test_plan_from_lives() {
+        let plan = plan();
+        // 未知的节次与无效的星期被跳过，按上课时间排序。
+        let ids = plan
+            .lectures()
+            .iter()
+            .map(|l| l.live_id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [2, 1]);
+        let first = &plan.lectures()[0];
+        let date = NaiveDate::from_ymd_opt(2024, 9, 9).unwrap();
+        assert_eq!(Some(first.start()), date_time_millis(date, time(10, 0)));
+        assert_eq!(Some(first.end()), date_time_millis(date, time(11, 30)));
+        assert_eq!(plan.lectures()[1].place(), "B-203");
+        assert_eq!(plan.lectures()[1].uids(), ["a", "b"]);
+    }
+    #[test]
+    fn test_next_wake() {
+        let mut plan = plan();
+        let at = |d, h, m| Local.with_ymd_and_hms(2024, 9, d, h, m, 0).unwrap();
+        let start = plan.lectures()[0].start();
+        assert_eq!(plan.next_wake(at(9, 7, 0)), Wake::Sleep(start));
+        assert_eq!(plan.next_wake(at(9, 10, 30)), Wake::Record(0));
+        // 下课后等待下一节课。
+        assert_eq!(
+            plan.next_wake(at(9, 12, 0)),
+            Wake::Sleep(plan.lectures()[1].start())
+        );
+        plan.lectures[1].status = LectureStatus::Recorded;
+        let next_monday = date_time_millis(
+            NaiveDate::from_ymd_opt(2024, 9, 16).unwrap(),
+            NaiveTime::MIN,
+        );
+        assert_eq!(
+            plan.next_wake(at(9, 12, 0)),
+            Wake::Sleep(next_monday.unwrap())
+        );
+    }
+}
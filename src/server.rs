@@ -0,0 +1,388 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 本地 HTTP 服务，以 JSON 提供教室、直播与录播信息，供多个工具共用同一组会话。
+//!
+//! 接口均只接受 `GET`:
+//! - `/rooms`: 所有教室及其设备码，见 [`Room::get_all_rooms`];
+//! - `/rooms/{name}/stream`: 教室的直播地址，没有会话也没有缓存的教室列表时 `name` 即为设备码；
+//! - `/now`: 各会话当前的直播，`/now?previous` 则为上一节课的直播；
+//! - `/courses/{live_id}/lessons`: 课程的所有课次；
+//! - `/lessons/{id}/recording`: 课次的录播地址。
+//!
+//! 出错时返回 `{"error": "..."}`. 成功的响应会被缓存，缓存时间见 [`ServerOptions`].
+
+use crate::{
+    lesson::Lesson, AnonymousClient, CancellationHandle, Live, NoProgress, PairVec, Room,
+    ScanOptions, SessionPool, TermError, Timetable,
+};
+use log::{info, warn};
+use percent_encoding::percent_decode_str;
+use std::{
+    collections::HashMap,
+    net::ToSocketAddrs,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// 响应的缓存时间。
+    pub cache_ttl: Duration,
+    /// 教室列表的缓存时间。扫描全部教室耗时较长，因此单独设置。
+    pub rooms_ttl: Duration,
+    /// 处理请求的线程数。
+    pub workers: usize,
+    pub scan: ScanOptions,
+    pub timetable: Timetable,
+}
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            cache_ttl: Duration::from_secs(60),
+            rooms_ttl: Duration::from_secs(24 * 60 * 60),
+            workers: 8,
+            scan: ScanOptions::default(),
+            timetable: Timetable::default(),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Route {
+    Rooms,
+    RoomStream(String),
+    Now { previous: bool },
+    Lessons(i64),
+    Recording(i64),
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ApiError {
+    status: u16,
+    message: String,
+}
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+    fn body(&self) -> String {
+        serde_json::json!({ "error": self.message }).to_string()
+    }
+}
+impl From<Box<ureq::Error>> for ApiError {
+    fn from(e: Box<ureq::Error>) -> Self {
+        ApiError::new(502, format!("请求出错：{e}"))
+    }
+}
+impl From<TermError> for ApiError {
+    fn from(e: TermError) -> Self {
+        ApiError::new(502, e.to_string())
+    }
+}
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::new(500, format!("序列化出错：{e}"))
+    }
+}
+fn parse_id(id: &str) -> Result<i64, ApiError> {
+    id.parse()
+        .map_err(|_| ApiError::new(400, format!("无效的编号：`{id}`")))
+}
+fn parse_route(method: &str, url: &str) -> Result<Route, ApiError> {
+    if method != "GET" {
+        return Err(ApiError::new(405, "只支持 GET 请求"));
+    }
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["rooms"] => Ok(Route::Rooms),
+        ["rooms", name, "stream"] => Ok(Route::RoomStream(
+            percent_decode_str(name).decode_utf8_lossy().into_owned(),
+        )),
+        ["now"] => Ok(Route::Now {
+            previous: query
+                .split('&')
+                .any(|p| matches!(p, "previous" | "previous=true" | "previous=1")),
+        }),
+        ["courses", live_id, "lessons"] => Ok(Route::Lessons(parse_id(live_id)?)),
+        ["lessons", id, "recording"] => Ok(Route::Recording(parse_id(id)?)),
+        _ => Err(ApiError::new(404, format!("未知的接口：`{path}`"))),
+    }
+}
+/// 缓存中的数据在处理请求的线程 panic 后仍然完整，因此忽略锁的中毒。
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+struct ApiInner {
+    pool: SessionPool,
+    options: ServerOptions,
+    client: AnonymousClient,
+    cache: Mutex<HashMap<Route, (Instant, String)>>,
+    rooms: Mutex<Option<(Instant, PairVec<String, String>)>>,
+    /// 扫描教室期间持有，使同时只进行一次扫描。
+    scan: Mutex<()>,
+}
+/// 本地 HTTP 服务，见模块文档。
+///
+/// 克隆后的服务共享缓存。
+#[derive(Clone)]
+pub struct ApiServer {
+    inner: Arc<ApiInner>,
+}
+impl ApiServer {
+    pub fn new(pool: SessionPool, options: ServerOptions) -> Self {
+        Self {
+            inner: Arc::new(ApiInner {
+                client: pool.anonymous_client(),
+                pool,
+                options,
+                cache: Mutex::new(HashMap::new()),
+                rooms: Mutex::new(None),
+                scan: Mutex::new(()),
+            }),
+        }
+    }
+    fn require_session(&self) -> Result<(), ApiError> {
+        match self.inner.pool.next() {
+            Some(_) => Ok(()),
+            None => Err(ApiError::new(503, "没有可用的会话")),
+        }
+    }
+    /// 未过期的教室列表。
+    fn fresh_rooms(&self) -> Option<PairVec<String, String>> {
+        lock(&self.inner.rooms)
+            .as_ref()
+            .filter(|(at, _)| at.elapsed() < self.inner.options.rooms_ttl)
+            .map(|(_, rooms)| rooms.clone())
+    }
+    /// 缓存的教室列表，过期后重新扫描，没有可用的会话时沿用过期的列表。
+    ///
+    /// 扫描时不持有缓存的锁，扫描期间的其它请求会等待扫描结果。
+    fn rooms(&self) -> Result<PairVec<String, String>, ApiError> {
+        if let Some(rooms) = self.fresh_rooms() {
+            return Ok(rooms);
+        }
+        let _scan = lock(&self.inner.scan);
+        // 等待期间其它请求可能已经完成了扫描。
+        if let Some(rooms) = self.fresh_rooms() {
+            return Ok(rooms);
+        }
+        if let Err(e) = self.require_session() {
+            let stale = lock(&self.inner.rooms);
+            return stale.as_ref().map(|(_, rooms)| rooms.clone()).ok_or(e);
+        }
+        let scanned = PairVec::from(Room::get_all_rooms_with(
+            &self.inner.pool,
+            &self.inner.options.scan,
+            &NoProgress::default(),
+        ));
+        *lock(&self.inner.rooms) = Some((Instant::now(), scanned.clone()));
+        Ok(scanned)
+    }
+    fn query(&self, route: &Route) -> Result<String, ApiError> {
+        let pool = &self.inner.pool;
+        let multi = NoProgress::default();
+        Ok(match route {
+            Route::Rooms => serde_json::to_string(&self.rooms()?)?,
+            Route::RoomStream(name) => {
+                let device_code = match self.rooms() {
+                    Ok(rooms) => rooms
+                        .get(name.as_str())
+                        .cloned()
+                        .ok_or_else(|| ApiError::new(404, format!("没有找到教室 `{name}`")))?,
+                    // 直播地址无需登录，没有教室列表时 `name` 即为设备码。
+                    Err(e) if e.status == 503 => name.clone(),
+                    Err(e) => return Err(e),
+                };
+                let video_path = crate::get_live_video_path(&self.inner.client, &device_code)?;
+                serde_json::to_string(&video_path)?
+            }
+            Route::Now { previous } => {
+                self.require_session()?;
+                let jie = self.inner.options.timetable.now_to_jie(*previous);
+                serde_json::to_string(&PairVec::from(Live::get_lives_by_jie(pool, jie, &multi)?))?
+            }
+            Route::Lessons(live_id) => {
+                self.require_session()?;
+                let lessons =
+                    pool.with_failover(|session| Lesson::get_lessons(session, *live_id))?;
+                serde_json::to_string(&lessons)?
+            }
+            Route::Recording(id) => {
+                let video_path = crate::get_recording_live_video_path(&self.inner.client, *id)?;
+                serde_json::to_string(&video_path)?
+            }
+        })
+    }
+    fn cached(&self, route: &Route) -> Result<String, ApiError> {
+        // 教室列表另有缓存。
+        if *route == Route::Rooms {
+            return self.query(route);
+        }
+        let ttl = self.inner.options.cache_ttl;
+        if let Some((at, body)) = lock(&self.inner.cache).get(route) {
+            if at.elapsed() < ttl {
+                return Ok(body.clone());
+            }
+        }
+        let body = self.query(route)?;
+        let mut cache = lock(&self.inner.cache);
+        cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        cache.insert(route.clone(), (Instant::now(), body.clone()));
+        Ok(body)
+    }
+    /// 处理一个请求，返回状态码与 JSON 响应体，便于嵌入其它 HTTP 服务。
+    pub fn respond(&self, method: &str, url: &str) -> (u16, String) {
+        match parse_route(method, url).and_then(|route| self.cached(&route)) {
+            Ok(body) => (200, body),
+            Err(e) => {
+                if e.status >= 500 {
+                    warn!("请求 `{url}` 处理出错：{}.", e.message);
+                }
+                (e.status, e.body())
+            }
+        }
+    }
+    fn handle(&self, request: tiny_http::Request) {
+        let (status, body) = self.respond(request.method().as_str(), request.url());
+        let response = tiny_http::Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header("Content-Type", "application/json; charset=utf-8"))
+            // 允许浏览器中的页面直接访问。
+            .with_header(header("Access-Control-Allow-Origin", "*"));
+        if let Err(e) = request.respond(response) {
+            warn!("响应发送失败：{e}.");
+        }
+    }
+    /// 在 `addr` 上提供服务，直到 `cancel` 被取消。
+    pub fn serve(
+        &self,
+        addr: impl ToSocketAddrs,
+        cancel: &CancellationHandle,
+    ) -> std::io::Result<()> {
+        let api = self.clone();
+        serve_requests(addr, self.inner.options.workers, cancel, move |request| {
+            api.handle(request)
+        })
+    }
+}
+pub(crate) fn header(name: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(name, value).unwrap()
+}
+/// 在 `addr` 上以 `workers` 个线程接收请求并交由 `handle` 处理，直到 `cancel` 被取消。
+pub(crate) fn serve_requests<H>(
+    addr: impl ToSocketAddrs,
+    workers: usize,
+    cancel: &CancellationHandle,
+    handle: H,
+) -> std::io::Result<()>
+where
+    H: Fn(tiny_http::Request) + Clone + Send + 'static,
+{
+    let server = Arc::new(tiny_http::Server::http(addr).map_err(std::io::Error::other)?);
+    info!("HTTP 服务已启动：http://{}.", server.server_addr());
+    let mut handles = Vec::new();
+    for _ in 0..workers.max(1) {
+        let server = Arc::clone(&server);
+        let handle = handle.clone();
+        let cancel = cancel.clone();
+        handles.push(std::thread::spawn(move || {
+            while !cancel.is_cancelled() {
+                match server.recv_timeout(Duration::from_millis(500)) {
+                    // 单个请求的 panic 不应使线程退出，panic 的信息已由默认的钩子输出。
+                    Ok(Some(request)) => {
+                        if std::panic::catch_unwind(AssertUnwindSafe(|| handle(request))).is_err() {
+                            warn!("请求处理时发生 panic.");
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(e) => {
+                        warn!("请求接收失败：{e}.");
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        if handle.join().is_err() {
+            warn!("处理请求的线程发生 panic.");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_route() {
+        assert_eq!(parse_route("GET", "/rooms/"), Ok(Route::Rooms));
+        assert_eq!(
+            parse_route("GET", "/rooms/B-203%20%E4%B8%9C/stream"),
+            Ok(Route::RoomStream("B-203 东".to_owned()))
+        );
+        assert_eq!(
+            parse_route("GET", "/now?x=1&previous"),
+            Ok(Route::Now { previous: true })
+        );
+        assert_eq!(
+            parse_route("GET", "/courses/42/lessons"),
+            Ok(Route::Lessons(42))
+        );
+        assert_eq!(
+            parse_route("GET", "/lessons/7/recording"),
+            Ok(Route::Recording(7))
+        );
+        assert_eq!(
+            parse_route("GET", "/lessons/x/recording")
+                .unwrap_err()
+                .status,
+            400
+        );
+        assert_eq!(parse_route("POST", "/rooms").unwrap_err().status, 405);
+        assert_eq!(parse_route("GET", "/").unwrap_err().status, 404);
+        let server = ApiServer::new(SessionPool::new(Vec::new()), ServerOptions::default());
+        assert_eq!(server.respond("GET", "/now").0, 503);
+        assert_eq!(server.respond("GET", "/rooms").0, 503);
+    }
+    #[test]
+    fn test_stale_rooms() {
+        let server = ApiServer::new(SessionPool::new(Vec::new()), ServerOptions::default());
+        let rooms = PairVec::from(HashMap::from([("B-203".to_owned(), "x".to_owned())]));
+        let stale = Instant::now().checked_sub(Duration::from_secs(2 * 24 * 60 * 60));
+        *lock(&server.inner.rooms) = stale.map(|at| (at, rooms));
+        // 没有会话时沿用过期的教室列表。
+        let (status, body) = server.respond("GET", "/rooms");
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("B-203"));
+        assert_eq!(server.respond("GET", "/rooms/A-101/stream").0, 404);
+        // 缓存的锁在 panic 后仍可使用。
+        let inner = Arc::clone(&server.inner);
+        let _ = std::thread::spawn(move || {
+            let _cache = inner.cache.lock().unwrap();
+            panic!();
+        })
+        .join();
+        assert_eq!(server.respond("GET", "/rooms").0, 200);
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{
+    protocol::{Redirected, Server},
+    tools::mutex_into_inner_error_handler,
+    AnonymousClient, ServerSession,
+};
+use chrono::{Datelike, Local};
+use cxlib_types::Session;
+use log::{debug, warn};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// 错误是否由会话本身引起，即未登录、登录过期（重定向到登录页，见 [`Redirected`]）或无权限。
+///
+/// 超时、DNS 与服务器的 5xx 等错误与账号无关，不应因此弃用会话。
+pub fn is_session_error(e: &ureq::Error) -> bool {
+    match e {
+        ureq::Error::StatusCode(401 | 403) => true,
+        ureq::Error::Other(e) => e
+            .downcast_ref::<Redirected>()
+            .is_some_and(Redirected::is_login),
+        _ => false,
+    }
+}
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionHealth {
+    /// 尚未检查。
+    Unchecked,
+    Healthy,
+    /// 未登录、会话过期或无权限。
+    Unhealthy,
+}
+impl SessionHealth {
+    /// 未检查的会话也视为可用。
+    pub fn is_usable(&self) -> bool {
+        !matches!(self, SessionHealth::Unhealthy)
+    }
+}
+struct SessionPoolInner {
+    server: Server,
+    sessions: Vec<ServerSession>,
+    health: Mutex<Vec<SessionHealth>>,
+    next: AtomicUsize,
+}
+/// 会话池。
+///
+/// 需要登录的公共查询（教室等）会在可用的会话之间轮转。会话失效（见 [`is_session_error`]）时
+/// 将被标记为不可用，并自动换用下一个会话重试；网络错误等与会话无关的错误则直接返回。
+///
+/// 池中的会话均使用同一平台，见 [`SessionPool::with_server`].
+/// 克隆开销很小，克隆后的会话池共享健康状态。
+#[derive(Clone)]
+pub struct SessionPool {
+    inner: Arc<SessionPoolInner>,
+}
+impl SessionPool {
+    /// 会话使用默认的平台。
+    pub fn new(sessions: impl IntoIterator<Item = Session>) -> Self {
+        Self::with_server(Server::default(), sessions)
+    }
+    /// 会话使用 `server` 指定的平台。
+    pub fn with_server(server: Server, sessions: impl IntoIterator<Item = Session>) -> Self {
+        let sessions = sessions
+            .into_iter()
+            .map(|session| ServerSession::new(session, server.clone()))
+            .collect::<Vec<_>>();
+        let health = vec![SessionHealth::Unchecked; sessions.len()];
+        Self {
+            inner: Arc::new(SessionPoolInner {
+                server,
+                sessions,
+                health: Mutex::new(health),
+                next: AtomicUsize::new(0),
+            }),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.inner.sessions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.inner.sessions.is_empty()
+    }
+    /// 池中所有会话，不论其健康状态。
+    pub fn sessions(&self) -> &[ServerSession] {
+        &self.inner.sessions
+    }
+    pub fn server(&self) -> &Server {
+        &self.inner.server
+    }
+    /// 请求同一平台的匿名客户端，供无需登录的接口使用。
+    pub fn anonymous_client(&self) -> AnonymousClient {
+        AnonymousClient::new().with_server(self.inner.server.clone())
+    }
+    fn health_vec(&self) -> Vec<SessionHealth> {
+        self.inner
+            .health
+            .lock()
+            .unwrap_or_else(mutex_into_inner_error_handler)
+            .clone()
+    }
+    fn set_health(&self, index: usize, health: SessionHealth) {
+        self.inner
+            .health
+            .lock()
+            .unwrap_or_else(mutex_into_inner_error_handler)[index] = health;
+    }
+    fn index_of(&self, session: &Session) -> Option<usize> {
+        self.inner.sessions.iter().position(|s| **s == *session)
+    }
+    /// 当前可用的会话。
+    pub fn usable_sessions(&self) -> Vec<&ServerSession> {
+        let health = self.health_vec();
+        self.inner
+            .sessions
+            .iter()
+            .zip(health)
+            .filter(|(_, h)| h.is_usable())
+            .map(|(s, _)| s)
+            .collect()
+    }
+    /// 以 `uid` 为键报告各会话的健康状态。
+    pub fn health(&self) -> HashMap<&str, SessionHealth> {
+        self.inner
+            .sessions
+            .iter()
+            .map(|s| s.uid())
+            .zip(self.health_vec())
+            .collect()
+    }
+    pub fn mark_healthy(&self, session: &Session) {
+        if let Some(index) = self.index_of(session) {
+            self.set_health(index, SessionHealth::Healthy);
+        }
+    }
+    pub fn mark_unhealthy(&self, session: &Session) {
+        if let Some(index) = self.index_of(session) {
+            warn!("会话 {}({}) 不可用。", session.name(), session.uid());
+            self.set_health(index, SessionHealth::Unhealthy);
+        }
+    }
+    /// 对每个会话发起一次开销较小的请求，并据此更新健康状态。
+    ///
+    /// 返回检查后的健康状态。
+    pub fn check_health(&self) -> HashMap<&str, SessionHealth> {
+        let year = Local::now().year();
+        for (index, session) in self.inner.sessions.iter().enumerate() {
+            let health = match crate::protocol::list_student_course_live_page(session, 1, year, 1) {
+                Ok(resp) => {
+                    if resp
+                        .into_body()
+                        .read_json::<Vec<serde_json::Value>>()
+                        .is_ok()
+                    {
+                        SessionHealth::Healthy
+                    } else {
+                        SessionHealth::Unhealthy
+                    }
+                }
+                Err(e) if is_session_error(&e) => {
+                    debug!("会话 {} 健康检查失败：{e}.", session.uid());
+                    SessionHealth::Unhealthy
+                }
+                // 与会话无关的错误无法说明会话的状态。
+                Err(e) => {
+                    debug!("会话 {} 健康检查失败：{e}.", session.uid());
+                    SessionHealth::Unchecked
+                }
+            };
+            self.set_health(index, health);
+        }
+        self.health()
+    }
+    /// 以轮转的方式取出下一个可用的会话。
+    pub fn next(&self) -> Option<&ServerSession> {
+        let health = self.health_vec();
+        let len = self.len();
+        for _ in 0..len {
+            let index = self.inner.next.fetch_add(1, Ordering::Relaxed) % len;
+            if health[index].is_usable() {
+                return Some(&self.inner.sessions[index]);
+            }
+        }
+        None
+    }
+    /// 使用可用的会话执行 `f`, 会话失效时将其标记为不可用并换用下一个会话。
+    ///
+    /// 其它错误直接返回，不影响会话的状态；所有会话均不可用时返回最后一次的错误。
+    pub fn with_failover<T>(
+        &self,
+        f: impl Fn(&ServerSession) -> Result<T, Box<ureq::Error>>,
+    ) -> Result<T, Box<ureq::Error>> {
+        let mut last_error = None;
+        while let Some(session) = self.next() {
+            match f(session) {
+                Ok(r) => {
+                    self.mark_healthy(session);
+                    return Ok(r);
+                }
+                Err(e) if is_session_error(&e) => {
+                    debug!("会话 {} 请求失败：{e}.", session.uid());
+                    self.mark_unhealthy(session);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            Box::new(ureq::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "没有可用的会话。",
+            )))
+        }))
+    }
+}
+impl FromIterator<Session> for SessionPool {
+    fn from_iter<T: IntoIterator<Item = Session>>(iter: T) -> Self {
+        Self::new(iter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use std::cell::Cell;
+
+    fn pool() -> SessionPool {
+        SessionPool::new([Session::new("1", "a"), Session::new("2", "b")])
+    }
+    fn status<T>(code: u16) -> Result<T, Box<ureq::Error>> {
+        Err(Box::new(ureq::Error::StatusCode(code)))
+    }
+
+    #[test]
+    fn test_next() {
+        let pool = pool();
+        let uids = (0..3)
+            .map(|_| pool.next().unwrap().uid())
+            .collect::<Vec<_>>();
+        assert_eq!(uids, ["1", "2", "1"]);
+        pool.mark_unhealthy(&pool.sessions()[1]);
+        assert_eq!(pool.health()["2"], SessionHealth::Unhealthy);
+        assert_eq!(pool.usable_sessions().len(), 1);
+        assert_eq!(pool.next().unwrap().uid(), "1");
+        assert_eq!(pool.next().unwrap().uid(), "1");
+        pool.mark_unhealthy(&pool.sessions()[0]);
+        assert!(pool.next().is_none());
+        pool.mark_healthy(&pool.sessions()[1]);
+        assert_eq!(pool.next().unwrap().uid(), "2");
+        assert_eq!(pool.next().unwrap().server(), &Server::default());
+
+        let server = Server::new("example.com", 1);
+        let pool = SessionPool::with_server(server.clone(), [Session::new("1", "a")]);
+        assert_eq!(pool.next().unwrap().server(), &server);
+        assert_eq!(pool.anonymous_client().server(), &server);
+    }
+    #[test]
+    fn test_with_failover() {
+        let pool = pool();
+        // 与会话无关的错误不换用会话，也不标记。
+        let calls = Cell::new(0);
+        let result = pool.with_failover(|_| {
+            calls.set(calls.get() + 1);
+            status::<()>(502)
+        });
+        assert!(matches!(*result.unwrap_err(), ureq::Error::StatusCode(502)));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(pool.usable_sessions().len(), 2);
+        // 会话失效时换用下一个会话。
+        let pool = self::pool();
+        let result = pool.with_failover(|session| match session.uid() {
+            "1" => status(401),
+            uid => Ok(uid.to_owned()),
+        });
+        assert_eq!(result.unwrap(), "2");
+        assert_eq!(pool.health()["1"], SessionHealth::Unhealthy);
+        assert_eq!(pool.health()["2"], SessionHealth::Healthy);
+        let result = pool.with_failover(|_| status::<()>(403));
+        assert!(matches!(*result.unwrap_err(), ureq::Error::StatusCode(403)));
+        assert!(pool.next().is_none());
+        let result = pool.with_failover(|_| Ok(()));
+        assert!(matches!(*result.unwrap_err(), ureq::Error::Io(_)));
+    }
+    #[test]
+    fn test_is_session_error() {
+        let redirected = |uri| ureq::Error::Other(Box::new(Redirected::new(uri)));
+        assert!(is_session_error(&redirected(
+            "https://passport2.chaoxing.com/login?refer=x"
+        )));
+        assert!(is_session_error(&redirected("http://a.com/sso/Login")));
+        assert!(!is_session_error(&redirected(
+            "http://cdn.example.com/a.json"
+        )));
+        assert!(!is_session_error(&ureq::Error::StatusCode(500)));
+    }
+}
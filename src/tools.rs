@@ -13,11 +13,23 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use chrono::{Local, Timelike};
-use cxlib_types::Session;
+use crate::{
+    hls::{HlsError, MediaPlaylist, Playlist},
+    output::{OutputError, OutputFormat},
+    Client,
+};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime};
 use log::{debug, error};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error as ErrorTrait, hash::Hash};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    error::Error as ErrorTrait,
+    fmt::{Display, Formatter},
+    hash::Hash,
+    ops::Index,
+    str::FromStr,
+};
 
 pub(crate) fn json_parsing_error_handler<T>(e: impl ErrorTrait) -> T {
     error!("json 解析出错！错误信息：{e}.");
@@ -35,13 +47,21 @@ pub(crate) fn mutex_into_inner_error_handler<T>(e: impl ErrorTrait) -> T {
     error!("保有互斥锁的其他线程发生 panic, 错误信息：{e}.");
     panic!()
 }
+/// 教室或课次的各路视频地址，平台返回的空地址视为不存在。
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct VideoPath {
+    #[serde(default, deserialize_with = "non_empty")]
     ppt_video: Option<String>,
+    #[serde(default, deserialize_with = "non_empty")]
     teacher_full: Option<String>,
+    #[serde(default, deserialize_with = "non_empty")]
     teacher_track: Option<String>,
+    #[serde(default, deserialize_with = "non_empty")]
     student_full: Option<String>,
 }
+fn non_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.filter(|url| !url.is_empty()))
+}
 impl VideoPath {
     pub fn is_default(&self) -> bool {
         self.teacher_full.is_none()
@@ -61,6 +81,126 @@ impl VideoPath {
     pub fn student_full(&self) -> &str {
         self.student_full.as_deref().unwrap_or_default()
     }
+    fn field_mut(&mut self, kind: StreamKind) -> &mut Option<String> {
+        match kind {
+            StreamKind::PptVideo => &mut self.ppt_video,
+            StreamKind::TeacherFull => &mut self.teacher_full,
+            StreamKind::TeacherTrack => &mut self.teacher_track,
+            StreamKind::StudentFull => &mut self.student_full,
+        }
+    }
+    /// 获取 `kind` 对应的视频地址，不存在时返回 `None`.
+    pub fn get(&self, kind: StreamKind) -> Option<&str> {
+        match kind {
+            StreamKind::PptVideo => self.ppt_video.as_deref(),
+            StreamKind::TeacherFull => self.teacher_full.as_deref(),
+            StreamKind::TeacherTrack => self.teacher_track.as_deref(),
+            StreamKind::StudentFull => self.student_full.as_deref(),
+        }
+    }
+    /// 按 [`StreamKind::ALL`] 的顺序遍历存在的各路视频。
+    pub fn streams(&self) -> impl Iterator<Item = (StreamKind, &str)> {
+        StreamKind::ALL
+            .into_iter()
+            .filter_map(|kind| self.get(kind).map(|url| (kind, url)))
+    }
+    /// 按 `kinds` 的顺序遍历存在的各路视频及其标题，标题为 `name` 加视频类型，如 `B-203 ppt_video`.
+    pub fn titled_streams<'a>(
+        &'a self,
+        name: &'a str,
+        kinds: &'a [StreamKind],
+    ) -> impl Iterator<Item = (StreamKind, &'a str, String)> + 'a {
+        kinds.iter().filter_map(move |kind| {
+            self.get(*kind)
+                .map(|url| (*kind, url, format!("{name} {kind}")))
+        })
+    }
+    /// 合并两个不完整的 `VideoPath`, `self` 中缺失的视频地址由 `other` 补全。
+    pub fn merge(&mut self, other: VideoPath) {
+        for kind in StreamKind::ALL {
+            let field = self.field_mut(kind);
+            if field.is_none() {
+                *field = other.get(kind).map(str::to_owned);
+            }
+        }
+    }
+    /// 获取并解析 `kind` 对应的播放列表，该路视频不存在时返回 `Ok(None)`.
+    pub fn playlist(
+        &self,
+        client: &impl Client,
+        kind: StreamKind,
+    ) -> Result<Option<Playlist>, HlsError> {
+        self.get(kind)
+            .map(|url| Playlist::fetch(client, url))
+            .transpose()
+    }
+    /// 获取 `kind` 对应的媒体播放列表，若为主播放列表则选取码率最高的一项。
+    pub fn media_playlist(
+        &self,
+        client: &impl Client,
+        kind: StreamKind,
+    ) -> Result<Option<MediaPlaylist>, HlsError> {
+        self.get(kind)
+            .map(|url| Playlist::fetch_media(client, url))
+            .transpose()
+    }
+}
+/// [`VideoPath`] 中的各路视频。
+///
+/// 字符串形式与 [`VideoPath`] 序列化后的字段名一致，如 `teacher_track`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamKind {
+    PptVideo,
+    TeacherFull,
+    TeacherTrack,
+    StudentFull,
+}
+impl StreamKind {
+    pub const ALL: [StreamKind; 4] = [
+        StreamKind::PptVideo,
+        StreamKind::TeacherFull,
+        StreamKind::TeacherTrack,
+        StreamKind::StudentFull,
+    ];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamKind::PptVideo => "ppt_video",
+            StreamKind::TeacherFull => "teacher_full",
+            StreamKind::TeacherTrack => "teacher_track",
+            StreamKind::StudentFull => "student_full",
+        }
+    }
+}
+impl Display for StreamKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseStreamKindError(String);
+impl Display for ParseStreamKindError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "未知的视频类型：`{}`.", self.0)
+    }
+}
+impl ErrorTrait for ParseStreamKindError {}
+impl FromStr for StreamKind {
+    type Err = ParseStreamKindError;
+
+    /// 同时接受 `teacher_track`, `teacher-track` 与 `teacherTrack` 等写法。
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s
+            .trim()
+            .chars()
+            .filter(|c| *c != '_' && *c != '-')
+            .collect::<String>()
+            .to_lowercase();
+        StreamKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str().replace('_', "") == normalized)
+            .ok_or_else(|| ParseStreamKindError(s.to_owned()))
+    }
 }
 #[derive(Serialize, Default, Debug, Clone)]
 struct WebUrl {
@@ -79,13 +219,13 @@ fn web_url_to_video_path(url: &WebUrl) -> VideoPath {
         .to_string();
     #[derive(Deserialize)]
     struct VideoPathInternal {
-        #[serde(rename = "pptVideo")]
+        #[serde(rename = "pptVideo", default, deserialize_with = "non_empty")]
         ppt_video: Option<String>,
-        #[serde(rename = "teacherFull")]
+        #[serde(rename = "teacherFull", default, deserialize_with = "non_empty")]
         teacher_full: Option<String>,
-        #[serde(rename = "teacherTrack")]
+        #[serde(rename = "teacherTrack", default, deserialize_with = "non_empty")]
         teacher_track: Option<String>,
-        #[serde(rename = "studentFull")]
+        #[serde(rename = "studentFull", default, deserialize_with = "non_empty")]
         student_full: Option<String>,
     }
     #[derive(Deserialize)]
@@ -109,32 +249,35 @@ fn web_url_to_video_path(url: &WebUrl) -> VideoPath {
         student_full,
     }
 }
-fn get_live_web_url(session: &Session, device_code: &str) -> Result<WebUrl, Box<ureq::Error>> {
-    let url = crate::protocol::get_live_url(session, device_code)?
+fn get_live_web_url(client: &impl Client, device_code: &str) -> Result<WebUrl, Box<ureq::Error>> {
+    let url = crate::protocol::get_live_url(client, device_code)?
         .into_body()
         .read_to_string()
         .unwrap_or_else(resp_parsing_error_handler);
     Ok(WebUrl { url })
 }
-fn get_recording_live_web_url(session: &Session, live_id: i64) -> Result<WebUrl, Box<ureq::Error>> {
-    let url = crate::protocol::get_view_url_hls(session, live_id)?
+fn get_recording_live_web_url(
+    client: &impl Client,
+    live_id: i64,
+) -> Result<WebUrl, Box<ureq::Error>> {
+    let url = crate::protocol::get_view_url_hls(client, live_id)?
         .into_body()
         .read_to_string()
         .unwrap_or_else(resp_parsing_error_handler);
     Ok(WebUrl { url })
 }
 pub fn get_live_video_path(
-    session: &Session,
+    client: &impl Client,
     device_code: &str,
 ) -> Result<VideoPath, Box<ureq::Error>> {
-    let url = get_live_web_url(session, device_code);
+    let url = get_live_web_url(client, device_code);
     Ok(web_url_to_video_path(&url?))
 }
 pub fn get_recording_live_video_path(
-    session: &Session,
+    client: &impl Client,
     live_id: i64,
 ) -> Result<VideoPath, Box<ureq::Error>> {
-    let url = get_recording_live_web_url(session, live_id);
+    let url = get_recording_live_web_url(client, live_id);
     Ok(web_url_to_video_path(&url?))
 }
 pub fn year_to_semester_id(year: i32, term: i32) -> i32 {
@@ -147,128 +290,213 @@ pub fn year_to_semester_id(year: i32, term: i32) -> i32 {
     r
 }
 pub fn date_count_to_year_term_week(now_year: i32, date_count: i32) -> (i32, i32, i64) {
+    date_count_to_year_term_week_since(now_year - 6, date_count)
+}
+/// 以 `first_year` 年第二学期为起点，将序号 `date_count` 换算为学年、学期与周次，每学期按 30 周计。
+pub fn date_count_to_year_term_week_since(first_year: i32, date_count: i32) -> (i32, i32, i64) {
     (
-        now_year - 6 + (date_count / 30) % 2 + date_count / 60,
+        first_year + (date_count / 30) % 2 + date_count / 60,
         2 - (date_count / 30) % 2,
         date_count as i64 % 30 + 1,
     )
 }
-// pub fn out<S: Serialize>(contents: &S, path: Option<PathBuf>) {
-//     let contents = toml::to_string_pretty(contents).unwrap();
-//     if let Some(path) = path {
-//         std::fs::write(path, contents).expect("写入内容出错！");
-//     } else {
-//         debug!("{contents}")
-//     }
-// }
+/// 当前（或上一）大节的第一小节，分界为 [`SECTION_TIMES`] 中的下课时间，见 [`Timetable::jie_at`].
 pub fn now_to_jie(previous: bool) -> i32 {
-    fn now_to_jie_internal() -> i32 {
-        let date_time = Local::now();
-        let s1 = Local::now().with_hour(10).unwrap().with_minute(5).unwrap();
-        let s3 = Local::now().with_hour(12).unwrap().with_minute(0).unwrap();
-        let s5 = Local::now().with_hour(15).unwrap().with_minute(35).unwrap();
-        let s7 = Local::now().with_hour(17).unwrap().with_minute(30).unwrap();
-        let s9 = Local::now().with_hour(20).unwrap().with_minute(35).unwrap();
-        if date_time < s1 {
-            1
-        } else if date_time < s3 {
-            3
-        } else if date_time < s5 {
-            5
-        } else if date_time < s7 {
-            7
-        } else if date_time < s9 {
-            9
-        } else {
-            11
-        }
+    Timetable::default().now_to_jie(previous)
+}
+/// 各大节的上课与下课时间，依次对应从第 1, 3, 5, 7, 9 节开始的大节。
+pub const SECTION_TIMES: [((u32, u32), (u32, u32)); 5] = [
+    ((8, 30), (10, 5)),
+    ((10, 25), (12, 0)),
+    ((14, 0), (15, 35)),
+    ((15, 55), (17, 30)),
+    ((19, 0), (20, 35)),
+];
+/// 以 `HH:MM` 形式（反）序列化时间。
+mod hour_minute {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format("%H:%M"))
     }
-    if previous {
-        match now_to_jie_internal() - 2 {
-            -1 => 1,
-            a => a,
-        }
-    } else {
-        match now_to_jie_internal() {
-            11 => 9,
-            a => a,
-        }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&s, "%H:%M").map_err(serde::de::Error::custom)
+    }
+}
+/// 一个大节。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// 大节的第一小节。
+    jie: i32,
+    #[serde(with = "hour_minute")]
+    start: NaiveTime,
+    #[serde(with = "hour_minute")]
+    end: NaiveTime,
+}
+impl Section {
+    pub fn new(jie: i32, start: NaiveTime, end: NaiveTime) -> Self {
+        Self { jie, start, end }
+    }
+    pub fn jie(&self) -> i32 {
+        self.jie
+    }
+    pub fn start(&self) -> NaiveTime {
+        self.start
+    }
+    pub fn end(&self) -> NaiveTime {
+        self.end
+    }
+}
+/// 作息时间表，默认值见 [`SECTION_TIMES`].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Timetable {
+    sections: Vec<Section>,
+}
+impl Timetable {
+    pub fn new(sections: Vec<Section>) -> Self {
+        Self { sections }
+    }
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
     }
+    /// 包含第 `jie` 小节的大节的上课与下课时间。
+    pub fn range(&self, jie: i32) -> Option<(NaiveTime, NaiveTime)> {
+        self.sections
+            .iter()
+            .find(|s| s.jie == jie || s.jie + 1 == jie)
+            .map(|s| (s.start, s.end))
+    }
+    /// `time` 所在大节的第一小节，以下课时间为分界，课间算作下一大节。
+    ///
+    /// `previous` 为 `true` 时取上一大节。第一大节之前与最后一大节之后分别取第一与最后一大节。
+    pub fn jie_at(&self, time: NaiveTime, previous: bool) -> i32 {
+        let last = self.sections.len().saturating_sub(1);
+        let index = match self.sections.iter().position(|s| time < s.end) {
+            Some(i) if previous => i.saturating_sub(1),
+            Some(i) => i,
+            None => last,
+        };
+        self.sections.get(index).map(|s| s.jie).unwrap_or(1)
+    }
+    pub fn now_to_jie(&self, previous: bool) -> i32 {
+        self.jie_at(Local::now().time(), previous)
+    }
+    /// 今天包含第 `jie` 小节的大节的下课时间。
+    pub fn end_today(&self, jie: i32) -> Option<DateTime<Local>> {
+        let (_, end) = self.range(jie)?;
+        Local::now()
+            .date_naive()
+            .and_time(end)
+            .and_local_timezone(Local)
+            .single()
+    }
+}
+impl Default for Timetable {
+    fn default() -> Self {
+        let sections = SECTION_TIMES
+            .iter()
+            .enumerate()
+            .filter_map(|(i, ((h1, m1), (h2, m2)))| {
+                Some(Section {
+                    jie: 2 * i as i32 + 1,
+                    start: NaiveTime::from_hms_opt(*h1, *m1, 0)?,
+                    end: NaiveTime::from_hms_opt(*h2, *m2, 0)?,
+                })
+            })
+            .collect();
+        Self { sections }
+    }
+}
+/// 从第 `jie` 节开始的大节的上课与下课时间。
+pub fn jie_to_time_range(jie: i32) -> Option<(NaiveTime, NaiveTime)> {
+    Timetable::default().range(jie)
+}
+/// 今天从第 `jie` 节开始的大节的下课时间，自定义的作息时间见 [`Timetable::end_today`].
+pub fn jie_end_today(jie: i32) -> Option<DateTime<Local>> {
+    Timetable::default().end_today(jie)
 }
 pub fn map_sort_by_key<K: Ord + Hash, V>(map: HashMap<K, V>) -> Vec<(K, V)> {
     let mut map = map.into_iter().collect::<Vec<_>>();
     map.sort_by(|x, y| x.0.cmp(&y.0));
     map.into_iter().collect()
 }
-pub fn term_year_detail(session: &Session) -> (i32, i32, i64) {
+/// 学期信息获取失败的原因。
+#[derive(Debug)]
+pub enum TermError {
+    Request(Box<ureq::Error>),
+    /// 平台返回的学期信息无法解析。
+    Parse(String),
+}
+impl Display for TermError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TermError::Request(e) => write!(f, "学期信息请求失败：{e}"),
+            TermError::Parse(message) => write!(f, "学期信息解析失败：{message}"),
+        }
+    }
+}
+impl ErrorTrait for TermError {}
+impl From<Box<ureq::Error>> for TermError {
+    fn from(e: Box<ureq::Error>) -> Self {
+        TermError::Request(e)
+    }
+}
+/// 学期第一周第一天的日期。`term` 为 1 的学期始于 `term_year` 年秋季，为 2 的学期始于次年春季。
+pub fn term_begin_date(
+    client: &impl Client,
+    term_year: i32,
+    term: i32,
+) -> Result<NaiveDate, TermError> {
     #[derive(Deserialize)]
     struct WeekDetail {
         date1: String,
     }
-    fn date_number(month: u32, day: u32) -> u32 {
-        month * 100 + day
-    }
-    fn str_to_date_number(date: &str) -> u32 {
-        let date = date.split('-').map(|s| s.trim()).collect::<Vec<_>>();
-        let month = date[0].parse::<u32>().unwrap();
-        let day = date[1].parse::<u32>().unwrap();
-        date_number(month, day)
-    }
-    // 当前时间。
-    let data_time = chrono::DateTime::<Local>::from(std::time::SystemTime::now());
-    // 当前年份。
-    let year = chrono::Datelike::year(&data_time);
-    // 当前年份前半年的学期 id.
-    let semester_id1 = year_to_semester_id(year - 1, 2);
-    // 当前年份后半年的学期 id.
-    let semester_id2 = year_to_semester_id(year, 1);
-    let WeekDetail { date1, .. } = crate::protocol::get_week_detail(session, 1, semester_id1)
-        .unwrap()
+    let semester_id = year_to_semester_id(term_year, term);
+    let WeekDetail { date1 } = crate::protocol::get_week_detail(client, 1, semester_id)?
         .into_body()
         .read_json()
-        .unwrap();
-    // 转换为可直接比较的数字。
-    let date_number1 = str_to_date_number(&date1);
-    let date_number2 = if let Ok(w) = crate::protocol::get_week_detail(session, 1, semester_id2) {
-        let WeekDetail { date1: date2, .. } = w.into_body().read_json().unwrap();
-        str_to_date_number(&date2)
-    } else {
-        u32::MAX
+        .map_err(|e| TermError::Parse(e.to_string()))?;
+    let year = if term == 2 { term_year + 1 } else { term_year };
+    date1
+        .split_once('-')
+        .and_then(|(month, day)| {
+            NaiveDate::from_ymd_opt(year, month.trim().parse().ok()?, day.trim().parse().ok()?)
+        })
+        .ok_or_else(|| TermError::Parse(format!("无效的日期 `{date1}`")))
+}
+/// 当前的学年、学期与周次。
+pub fn term_year_detail(client: &impl Client) -> Result<(i32, i32, i64), TermError> {
+    let today = Local::now().date_naive();
+    let year = today.year();
+    // 今年春季学期与秋季学期的开始日期，秋季学期的信息可能尚未发布。
+    let spring = term_begin_date(client, year - 1, 2)?;
+    let autumn = match term_begin_date(client, year, 1) {
+        Ok(date) => Some(date),
+        Err(TermError::Request(e)) if matches!(*e, ureq::Error::StatusCode(_)) => {
+            debug!("term_year_detail: {year} 年秋季学期的信息尚未发布：{e}.");
+            None
+        }
+        Err(e) => return Err(e),
     };
-    let date_number = date_number(
-        chrono::Datelike::month(&data_time),
-        chrono::Datelike::day(&data_time),
-    );
-    // 两日期之间为上半年的学期，之后为下半年学期，之前则是去年的学期。
-    let (term_begin_data_number, term_year, term) =
-        if (date_number1..date_number2).contains(&date_number) {
-            (date_number1, year - 1, 2)
-        } else if date_number2 <= date_number {
-            (date_number2, year, 1)
-        } else {
-            let semester_id = year_to_semester_id(year - 1, 1);
-            let WeekDetail { date1: date, .. } =
-                crate::protocol::get_week_detail(session, 1, semester_id)
-                    .unwrap()
-                    .into_body()
-                    .read_json()
-                    .unwrap();
-            (str_to_date_number(&date), year - 1, 1)
-        };
-    let month = term_begin_data_number / 100;
-    let day = term_begin_data_number % 100;
-    let term_begin_data_time = <chrono::DateTime<Local> as std::str::FromStr>::from_str(&format!(
-        "{year}-{month}-{day}T00:00:00.0+08:00"
-    ))
-    .unwrap();
-    let week = data_time
-        .signed_duration_since(term_begin_data_time)
-        .num_weeks()
-        + 1;
+    // 秋季学期开始之后为秋季学期，春季学期开始之后为春季学期，之前则是去年的秋季学期。
+    let (begin, term_year, term) = match autumn {
+        Some(autumn) if autumn <= today => (autumn, year, 1),
+        _ if spring <= today => (spring, year - 1, 2),
+        _ => (term_begin_date(client, year - 1, 1)?, year - 1, 1),
+    };
+    let week = (today - begin).num_weeks() + 1;
     debug!("term_year_detail: ({}, {}, {}).", term_year, term, week);
-    (term_year, term, week)
+    Ok((term_year, term, week))
 }
 
+/// 保持插入顺序的映射，序列化为 JSON 对象。
+///
+/// 键的查找与 [`PairVec::insert`] 是线性的，适用于课程、教室列表这样规模不大的结果；
+/// 以 [`FromIterator`], [`Extend`] 或反序列化批量构建时借助哈希表去重，耗时是线性的。
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PairVec<K, V> {
     vec: Vec<(K, V)>,
 }
@@ -276,6 +504,159 @@ impl<K, V> PairVec<K, V> {
     pub fn new(vec: Vec<(K, V)>) -> Self {
         Self { vec }
     }
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, (K, V)> {
+        self.vec.iter()
+    }
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.vec.iter().map(|(k, _)| k)
+    }
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.vec.iter().map(|(_, v)| v)
+    }
+    pub fn into_inner(self) -> Vec<(K, V)> {
+        self.vec
+    }
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: PartialEq + ?Sized,
+    {
+        self.vec
+            .iter()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| v)
+    }
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: PartialEq + ?Sized,
+    {
+        self.vec
+            .iter_mut()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| v)
+    }
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: PartialEq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+    /// 插入键值对。键已存在时替换其值并保持原有位置，返回旧值。
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        K: PartialEq,
+    {
+        match self.get_mut(&key) {
+            Some(v) => Some(std::mem::replace(v, value)),
+            None => {
+                self.vec.push((key, value));
+                None
+            }
+        }
+    }
+    /// 去除重复的键，各键保持首次出现的位置，值以最后出现的为准。
+    fn dedup(&mut self)
+    where
+        K: Hash + Eq,
+    {
+        // 每个位置上的键首次出现的位置。
+        let first = {
+            let mut seen = HashMap::with_capacity(self.vec.len());
+            self.vec
+                .iter()
+                .enumerate()
+                .map(|(i, (k, _))| *seen.entry(k).or_insert(i))
+                .collect::<Vec<_>>()
+        };
+        if first.iter().enumerate().all(|(i, f)| i == *f) {
+            return;
+        }
+        let mut slots: Vec<Option<(K, V)>> = Vec::with_capacity(self.vec.len());
+        for (i, (k, v)) in std::mem::take(&mut self.vec).into_iter().enumerate() {
+            if first[i] == i {
+                slots.push(Some((k, v)));
+            } else {
+                if let Some((_, slot)) = &mut slots[first[i]] {
+                    *slot = v;
+                }
+                slots.push(None);
+            }
+        }
+        self.vec = slots.into_iter().flatten().collect();
+    }
+    /// 按键排序。
+    pub fn sort(&mut self)
+    where
+        K: Ord,
+    {
+        self.vec.sort_by(|x, y| x.0.cmp(&y.0));
+    }
+}
+impl<K, V> Default for PairVec<K, V> {
+    fn default() -> Self {
+        Self { vec: Vec::new() }
+    }
+}
+/// 同 [`map_sort_by_key`], 按键排序。
+impl<K: Ord + Hash, V> From<HashMap<K, V>> for PairVec<K, V> {
+    fn from(map: HashMap<K, V>) -> Self {
+        Self::new(map_sort_by_key(map))
+    }
+}
+impl<K, V> From<Vec<(K, V)>> for PairVec<K, V> {
+    fn from(vec: Vec<(K, V)>) -> Self {
+        Self::new(vec)
+    }
+}
+/// 与 [`PairVec::insert`] 相同，已有的键会被替换，因此可用于合并多次运行的结果。
+impl<K: Hash + Eq, V> Extend<(K, V)> for PairVec<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        self.vec.extend(iter);
+        self.dedup();
+    }
+}
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for PairVec<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut pair_vec = PairVec::default();
+        pair_vec.extend(iter);
+        pair_vec
+    }
+}
+impl<K, V> IntoIterator for PairVec<K, V> {
+    type Item = (K, V);
+    type IntoIter = std::vec::IntoIter<(K, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.vec.into_iter()
+    }
+}
+impl<'a, K, V> IntoIterator for &'a PairVec<K, V> {
+    type Item = &'a (K, V);
+    type IntoIter = std::slice::Iter<'a, (K, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.vec.iter()
+    }
+}
+impl<K, V, Q> Index<&Q> for PairVec<K, V>
+where
+    K: Borrow<Q>,
+    Q: PartialEq + ?Sized,
+{
+    type Output = V;
+
+    /// 键不存在时 panic.
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("键不存在！")
+    }
 }
 impl<K: Serialize, V: Serialize> Serialize for PairVec<K, V> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -290,19 +671,139 @@ impl<K: Serialize, V: Serialize> Serialize for PairVec<K, V> {
         map.end()
     }
 }
-pub fn out<S: Serialize>(contents: &S, path: Option<std::path::PathBuf>) {
-    let contents = serde_json::to_string_pretty(contents).unwrap();
+/// 保持键在输入中的顺序，重复的键以后出现的为准。
+impl<'de, K, V> Deserialize<'de> for PairVec<K, V>
+where
+    K: Deserialize<'de> + Hash + Eq,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct PairVecVisitor<K, V>(std::marker::PhantomData<(K, V)>);
+        impl<'de, K, V> serde::de::Visitor<'de> for PairVecVisitor<K, V>
+        where
+            K: Deserialize<'de> + Hash + Eq,
+            V: Deserialize<'de>,
+        {
+            type Value = PairVec<K, V>;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("a map")
+            }
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut vec = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some(entry) = map.next_entry()? {
+                    vec.push(entry);
+                }
+                let mut pair_vec = PairVec::new(vec);
+                pair_vec.dedup();
+                Ok(pair_vec)
+            }
+        }
+        deserializer.deserialize_map(PairVecVisitor(std::marker::PhantomData))
+    }
+}
+/// 以 `format` 格式将结果写入 `path`, 未指定路径时输出到标准输出。
+pub fn out<S: Serialize>(
+    contents: &S,
+    path: Option<std::path::PathBuf>,
+    format: OutputFormat,
+) -> Result<(), OutputError> {
+    let contents = crate::output::render(contents, format)?;
     if let Some(path) = path {
-        std::fs::write(path, contents).expect("写入内容出错！");
+        crate::output::write_atomic(&path, contents.as_bytes())?;
     } else {
-        println!("{contents}")
+        print!("{contents}")
     }
+    Ok(())
 }
 #[cfg(test)]
 mod tests {
-    use crate::tools::year_to_semester_id;
-    use chrono::Local;
+    use crate::tools::{year_to_semester_id, PairVec, StreamKind, Timetable, VideoPath};
+    use chrono::{Local, NaiveTime};
 
+    #[test]
+    fn test_stream_kind() {
+        for kind in StreamKind::ALL {
+            assert_eq!(kind.to_string().parse::<StreamKind>(), Ok(kind));
+        }
+        assert_eq!("teacherTrack".parse(), Ok(StreamKind::TeacherTrack));
+        assert_eq!("PPT-VIDEO".parse(), Ok(StreamKind::PptVideo));
+        assert!("teacher".parse::<StreamKind>().is_err());
+    }
+    #[test]
+    fn test_video_path_merge() {
+        let mut a = VideoPath {
+            ppt_video: Some("a".to_owned()),
+            ..Default::default()
+        };
+        let b = VideoPath {
+            ppt_video: Some("b".to_owned()),
+            student_full: Some("c".to_owned()),
+            ..Default::default()
+        };
+        a.merge(b);
+        assert_eq!(
+            a.streams().collect::<Vec<_>>(),
+            [(StreamKind::PptVideo, "a"), (StreamKind::StudentFull, "c")]
+        );
+        assert_eq!(a.get(StreamKind::TeacherFull), None);
+        let c: VideoPath =
+            serde_json::from_str(r#"{"ppt_video":"","teacher_full":null,"teacher_track":"t"}"#)
+                .unwrap();
+        assert_eq!(c.get(StreamKind::PptVideo), None);
+        assert_eq!(c.streams().count(), 1);
+        assert!(!c.is_default());
+    }
+
+    #[test]
+    fn test_jie_at() {
+        let timetable = Timetable::default();
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(timetable.jie_at(at(7, 0), false), 1);
+        assert_eq!(timetable.jie_at(at(7, 0), true), 1);
+        assert_eq!(timetable.jie_at(at(10, 5), false), 3);
+        assert_eq!(timetable.jie_at(at(10, 5), true), 1);
+        assert_eq!(timetable.jie_at(at(19, 30), true), 7);
+        assert_eq!(timetable.jie_at(at(22, 0), false), 9);
+        assert_eq!(timetable.jie_at(at(22, 0), true), 9);
+    }
+
+    #[test]
+    fn test_pair_vec() {
+        let json = r#"{"3":{"ppt_video":"a"},"1":{},"2":{}}"#;
+        let mut pair_vec: PairVec<i64, VideoPath> = serde_json::from_str(json).unwrap();
+        assert_eq!(pair_vec.keys().copied().collect::<Vec<_>>(), [3, 1, 2]);
+        assert_eq!(pair_vec[&3].get(StreamKind::PptVideo), Some("a"));
+        let other = PairVec::from(std::collections::HashMap::from([
+            (4, VideoPath::default()),
+            (3, VideoPath::default()),
+        ]));
+        assert_eq!(other.keys().copied().collect::<Vec<_>>(), [3, 4]);
+        pair_vec.extend(other);
+        assert_eq!(pair_vec.keys().copied().collect::<Vec<_>>(), [3, 1, 2, 4]);
+        assert!(pair_vec[&3].is_default());
+        let rooms: PairVec<String, String> = [("b", "1"), ("a", "2"), ("b", "3")]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        assert_eq!(
+            serde_json::to_string(&rooms).unwrap(),
+            r#"{"b":"3","a":"2"}"#
+        );
+        assert_eq!(rooms["a"], "2");
+        let many = (0..100_000)
+            .chain(0..10)
+            .map(|i| (i, i * 2))
+            .collect::<PairVec<_, _>>();
+        assert_eq!(many.len(), 100_000);
+        assert_eq!(many.keys().take(3).copied().collect::<Vec<_>>(), [0, 1, 2]);
+    }
     #[test]
     fn test_year_to_semester_id() {
         let data_time = chrono::DateTime::<Local>::from(std::time::SystemTime::now());
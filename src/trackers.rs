@@ -0,0 +1,467 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! 内置的 [`ProgressTracker`] 实现。
+//!
+//! 各实现均可通过 [`CancellationHandle`] 从其他线程取消。
+
+use crate::{ProgressItem, ProgressState, ProgressTracker, ProgressTrackerHolder};
+use log::{info, warn};
+use std::{
+    error::Error as ErrorTrait,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// 取消句柄，克隆后的句柄共享同一状态。
+///
+/// 调用 [`CancellationHandle::cancel`] 后，关联的进度的 [`ProgressTracker::go_on`] 均返回 `false`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationHandle {
+    cancelled: Arc<AtomicBool>,
+}
+impl CancellationHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// 不显示进度。
+#[derive(Debug, Clone, Default)]
+pub struct NoProgress {
+    cancel: CancellationHandle,
+}
+impl NoProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_cancellation(mut self, cancel: CancellationHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+}
+impl ProgressTrackerHolder<NoProgress> for NoProgress {
+    fn init(&self, _total: u64, _data: ProgressState) -> NoProgress {
+        self.clone()
+    }
+    fn remove_progress(&self, _progress: &NoProgress) {}
+}
+impl ProgressTracker for NoProgress {
+    fn inc(&self, _delta: u64) {}
+    fn go_on(&self) -> bool {
+        !self.cancel.is_cancelled()
+    }
+    fn finish(&self, _data: ProgressState) {}
+}
+
+/// 通过 `log` 输出进度，两次输出至少间隔 `interval`.
+#[derive(Debug, Clone)]
+pub struct LogProgress {
+    interval: Duration,
+    cancel: CancellationHandle,
+}
+impl LogProgress {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            cancel: CancellationHandle::default(),
+        }
+    }
+    pub fn with_cancellation(mut self, cancel: CancellationHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+}
+impl Default for LogProgress {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(5))
+    }
+}
+pub struct LogTracker {
+    /// 日志中的阶段名称，子进度以 ` / ` 接在父进度之后。
+    label: String,
+    total: u64,
+    done: AtomicU64,
+    last_log: Mutex<Instant>,
+    interval: Duration,
+    cancel: CancellationHandle,
+}
+impl LogProgress {
+    fn tracker(&self, label: String, total: u64) -> LogTracker {
+        info!("{label}：开始。");
+        LogTracker {
+            label,
+            total,
+            done: AtomicU64::new(0),
+            last_log: Mutex::new(Instant::now()),
+            interval: self.interval,
+            cancel: self.cancel.clone(),
+        }
+    }
+}
+impl ProgressTrackerHolder<LogTracker> for LogProgress {
+    fn init(&self, total: u64, data: ProgressState) -> LogTracker {
+        self.tracker(data.to_string(), total)
+    }
+    fn init_child(&self, parent: &LogTracker, total: u64, data: ProgressState) -> LogTracker {
+        self.tracker(format!("{} / {data}", parent.label), total)
+    }
+    fn remove_progress(&self, _progress: &LogTracker) {}
+}
+impl ProgressTracker for LogTracker {
+    fn inc(&self, delta: u64) {
+        let done = self.done.fetch_add(delta, Ordering::Relaxed) + delta;
+        let mut last_log = self.last_log.lock().unwrap();
+        if last_log.elapsed() >= self.interval {
+            *last_log = Instant::now();
+            if self.total > 0 {
+                info!("{}：{done}/{}.", self.label, self.total);
+            } else {
+                info!("{}：{done}.", self.label);
+            }
+        }
+    }
+    fn go_on(&self) -> bool {
+        !self.cancel.is_cancelled()
+    }
+    fn on_item(&self, result: Result<(), &dyn ErrorTrait>, item: &ProgressItem) {
+        if let Err(e) = result {
+            warn!("{}：{item}获取失败：{e}.", self.label);
+        }
+    }
+    fn finish(&self, _data: ProgressState) {
+        info!(
+            "{}：完成，共 {} 项。",
+            self.label,
+            self.done.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// [`ChannelProgress`] 发出的消息，`id` 用于区分同时存在的多个进度。
+#[derive(Debug, Clone)]
+pub enum ProgressMessage {
+    Started {
+        id: usize,
+        /// 父进度的 `id`.
+        parent: Option<usize>,
+        total: u64,
+        state: ProgressState,
+    },
+    Inc {
+        id: usize,
+        delta: u64,
+    },
+    Item {
+        id: usize,
+        item: ProgressItem,
+        /// 失败时为错误信息。
+        error: Option<String>,
+    },
+    Finished {
+        id: usize,
+        state: ProgressState,
+    },
+    Removed {
+        id: usize,
+    },
+}
+/// 将进度以 [`ProgressMessage`] 发送到通道，适合由图形界面等在其他线程中显示。
+///
+/// 接收端被丢弃后，消息会被忽略。
+pub struct ChannelProgress {
+    sender: Sender<ProgressMessage>,
+    next_id: AtomicUsize,
+    cancel: CancellationHandle,
+}
+impl ChannelProgress {
+    pub fn new() -> (Self, Receiver<ProgressMessage>) {
+        let (sender, receiver) = channel();
+        (
+            Self {
+                sender,
+                next_id: AtomicUsize::new(0),
+                cancel: CancellationHandle::default(),
+            },
+            receiver,
+        )
+    }
+    pub fn with_cancellation(mut self, cancel: CancellationHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+}
+pub struct ChannelTracker {
+    id: usize,
+    sender: Sender<ProgressMessage>,
+    cancel: CancellationHandle,
+}
+impl ChannelTracker {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+impl ChannelProgress {
+    fn tracker(&self, parent: Option<usize>, total: u64, data: ProgressState) -> ChannelTracker {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self.sender.send(ProgressMessage::Started {
+            id,
+            parent,
+            total,
+            state: data,
+        });
+        ChannelTracker {
+            id,
+            sender: self.sender.clone(),
+            cancel: self.cancel.clone(),
+        }
+    }
+}
+impl ProgressTrackerHolder<ChannelTracker> for ChannelProgress {
+    fn init(&self, total: u64, data: ProgressState) -> ChannelTracker {
+        self.tracker(None, total, data)
+    }
+    fn init_child(
+        &self,
+        parent: &ChannelTracker,
+        total: u64,
+        data: ProgressState,
+    ) -> ChannelTracker {
+        self.tracker(Some(parent.id), total, data)
+    }
+    fn remove_progress(&self, progress: &ChannelTracker) {
+        let _ = self
+            .sender
+            .send(ProgressMessage::Removed { id: progress.id });
+    }
+}
+impl ProgressTracker for ChannelTracker {
+    fn inc(&self, delta: u64) {
+        let _ = self
+            .sender
+            .send(ProgressMessage::Inc { id: self.id, delta });
+    }
+    fn go_on(&self) -> bool {
+        !self.cancel.is_cancelled()
+    }
+    fn on_item(&self, result: Result<(), &dyn ErrorTrait>, item: &ProgressItem) {
+        let _ = self.sender.send(ProgressMessage::Item {
+            id: self.id,
+            item: item.clone(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+    fn finish(&self, data: ProgressState) {
+        let _ = self.sender.send(ProgressMessage::Finished {
+            id: self.id,
+            state: data,
+        });
+    }
+}
+
+/// 某一时刻的进度。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressSnapshot {
+    pub id: usize,
+    /// 父进度的 `id`.
+    pub parent: Option<usize>,
+    pub state: ProgressState,
+    pub total: u64,
+    pub done: u64,
+    pub failed: u64,
+    pub elapsed: Duration,
+    /// 每秒完成的项数。
+    pub rate: f64,
+    /// 预计剩余时间，总数未知或尚无进度时为 `None`.
+    pub eta: Option<Duration>,
+    pub finished: bool,
+}
+struct Counter {
+    id: usize,
+    parent: Option<usize>,
+    state: ProgressState,
+    total: u64,
+    done: AtomicU64,
+    failed: AtomicU64,
+    started: Instant,
+    finished: AtomicBool,
+}
+impl Counter {
+    fn snapshot(&self) -> ProgressSnapshot {
+        let done = self.done.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed();
+        let rate = if elapsed.is_zero() {
+            0.0
+        } else {
+            done as f64 / elapsed.as_secs_f64()
+        };
+        let eta = (self.total > done && rate > 0.0)
+            .then(|| Duration::from_secs_f64((self.total - done) as f64 / rate));
+        ProgressSnapshot {
+            id: self.id,
+            parent: self.parent,
+            state: self.state.clone(),
+            total: self.total,
+            done,
+            failed: self.failed.load(Ordering::Relaxed),
+            elapsed,
+            rate,
+            eta,
+            finished: self.finished.load(Ordering::Relaxed),
+        }
+    }
+}
+/// 以原子计数器记录进度，可随时通过 [`CounterProgress::snapshots`] 获取各进度的快照。
+#[derive(Default)]
+pub struct CounterProgress {
+    counters: Mutex<Vec<Arc<Counter>>>,
+    next_id: AtomicUsize,
+    cancel: CancellationHandle,
+}
+impl CounterProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_cancellation(mut self, cancel: CancellationHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+    /// 尚未被移除的各进度的快照，按创建顺序排列。
+    pub fn snapshots(&self) -> Vec<ProgressSnapshot> {
+        self.counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| c.snapshot())
+            .collect()
+    }
+}
+pub struct CounterTracker {
+    counter: Arc<Counter>,
+    cancel: CancellationHandle,
+}
+impl CounterTracker {
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.counter.snapshot()
+    }
+}
+impl CounterProgress {
+    fn tracker(&self, parent: Option<usize>, total: u64, data: ProgressState) -> CounterTracker {
+        let counter = Arc::new(Counter {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            parent,
+            state: data,
+            total,
+            done: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            started: Instant::now(),
+            finished: AtomicBool::new(false),
+        });
+        self.counters.lock().unwrap().push(Arc::clone(&counter));
+        CounterTracker {
+            counter,
+            cancel: self.cancel.clone(),
+        }
+    }
+}
+impl ProgressTrackerHolder<CounterTracker> for CounterProgress {
+    fn init(&self, total: u64, data: ProgressState) -> CounterTracker {
+        self.tracker(None, total, data)
+    }
+    fn init_child(
+        &self,
+        parent: &CounterTracker,
+        total: u64,
+        data: ProgressState,
+    ) -> CounterTracker {
+        self.tracker(Some(parent.counter.id), total, data)
+    }
+    fn remove_progress(&self, progress: &CounterTracker) {
+        self.counters
+            .lock()
+            .unwrap()
+            .retain(|c| !Arc::ptr_eq(c, &progress.counter));
+    }
+}
+impl ProgressTracker for CounterTracker {
+    fn inc(&self, delta: u64) {
+        self.counter.done.fetch_add(delta, Ordering::Relaxed);
+    }
+    fn go_on(&self) -> bool {
+        !self.cancel.is_cancelled()
+    }
+    fn on_item(&self, result: Result<(), &dyn ErrorTrait>, _item: &ProgressItem) {
+        if result.is_err() {
+            self.counter.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    fn finish(&self, _data: ProgressState) {
+        self.counter.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_trackers() {
+        let cancel = CancellationHandle::new();
+        let holder = CounterProgress::new().with_cancellation(cancel.clone());
+        let operation = holder.init(2, ProgressState::GetAllRooms);
+        let pb = holder.init_child(&operation, 4, ProgressState::GetDeviceCodes);
+        pb.inc(2);
+        let error = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        pb.on_item(Err(&error), &ProgressItem::Room { live_id: 1 });
+        let snapshot = holder.snapshots().remove(1);
+        assert_eq!(snapshot.parent, Some(operation.snapshot().id));
+        assert_eq!((snapshot.done, snapshot.failed), (2, 1));
+        assert!(!snapshot.finished);
+        assert!(pb.go_on());
+        std::thread::spawn(move || cancel.cancel()).join().unwrap();
+        assert!(!pb.go_on());
+        pb.finish(ProgressState::GetDeviceCodes);
+        assert!(pb.snapshot().finished);
+        holder.remove_progress(&pb);
+        assert_eq!(holder.snapshots().len(), 1);
+
+        let (holder, receiver) = ChannelProgress::new();
+        let pb = holder.init(1, ProgressState::GetLiveUrls);
+        pb.on_item(Err(&error), &ProgressItem::LiveUrl { live_id: 2 });
+        pb.inc(1);
+        holder.remove_progress(&pb);
+        drop((holder, pb));
+        let messages = receiver.iter().collect::<Vec<_>>();
+        assert_eq!(messages.len(), 4);
+        assert!(matches!(
+            &messages[1],
+            ProgressMessage::Item {
+                id: 0,
+                error: Some(_),
+                ..
+            }
+        ));
+    }
+}
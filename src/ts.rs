@@ -0,0 +1,358 @@
+// Copyright (C) 2024 learturely <learturely@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! MPEG-TS 分片的拼接。
+//!
+//! 将 HLS 分片按顺序合并为单个可播放的 `.ts` 文件，并修正连续性计数器；
+//! 可选地在不连续处改写 PTS/DTS/PCR, 使时间戳单调递增。
+
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+/// PTS/DTS 为 33 位，以 90kHz 计。
+const TIMESTAMP_MODULUS: i64 = 1 << 33;
+/// 不连续处前后两个分片之间留出的间隔，约为一帧。
+const DISCONTINUITY_GAP: i64 = 3600;
+/// 未标记不连续的分片，时间戳回退超过该值（一秒）时同样视为不连续。
+const BACKWARD_JUMP_THRESHOLD: i64 = 90000;
+
+#[derive(Debug, Clone, Default)]
+pub struct ConcatOptions {
+    /// 是否改写时间戳使其单调递增。
+    pub rewrite_timestamps: bool,
+}
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConcatStats {
+    pub segments: usize,
+    pub packets: u64,
+    /// 因无法同步而丢弃的字节数。
+    pub skipped_bytes: u64,
+    /// 改写了时间戳的不连续处个数。
+    pub rewritten_boundaries: usize,
+}
+
+fn read_timestamp(b: &[u8]) -> i64 {
+    (((b[0] as i64) >> 1) & 0x07) << 30
+        | (b[1] as i64) << 22
+        | ((b[2] as i64) >> 1) << 15
+        | (b[3] as i64) << 7
+        | (b[4] as i64) >> 1
+}
+fn write_timestamp(b: &mut [u8], ts: i64) {
+    b[0] = (b[0] & 0xF0) | ((ts >> 29) as u8 & 0x0E) | 1;
+    b[1] = (ts >> 22) as u8;
+    b[2] = ((ts >> 14) as u8 & 0xFE) | 1;
+    b[3] = (ts >> 7) as u8;
+    b[4] = ((ts << 1) as u8 & 0xFE) | 1;
+}
+fn read_pcr_base(b: &[u8]) -> i64 {
+    (b[0] as i64) << 25
+        | (b[1] as i64) << 17
+        | (b[2] as i64) << 9
+        | (b[3] as i64) << 1
+        | (b[4] as i64) >> 7
+}
+fn write_pcr_base(b: &mut [u8], base: i64) {
+    b[0] = (base >> 25) as u8;
+    b[1] = (base >> 17) as u8;
+    b[2] = (base >> 9) as u8;
+    b[3] = (base >> 1) as u8;
+    b[4] = (((base & 1) as u8) << 7) | (b[4] & 0x7F);
+}
+/// `a - b`, 考虑 33 位回绕。
+fn timestamp_diff(a: i64, b: i64) -> i64 {
+    let d = (a - b).rem_euclid(TIMESTAMP_MODULUS);
+    if d >= TIMESTAMP_MODULUS / 2 {
+        d - TIMESTAMP_MODULUS
+    } else {
+        d
+    }
+}
+fn shift(ts: i64, offset: i64) -> i64 {
+    (ts + offset).rem_euclid(TIMESTAMP_MODULUS)
+}
+
+/// 单个 TS 包中各字段的位置。
+struct Packet {
+    pid: u16,
+    has_payload: bool,
+    /// PCR 在包中的起始位置。
+    pcr: Option<usize>,
+    /// PTS 与 DTS 在包中的起始位置。
+    pts: Option<usize>,
+    dts: Option<usize>,
+}
+impl Packet {
+    fn parse(p: &[u8]) -> Packet {
+        let pid = (((p[1] & 0x1F) as u16) << 8) | p[2] as u16;
+        let pusi = p[1] & 0x40 != 0;
+        let afc = (p[3] >> 4) & 0x03;
+        let has_payload = afc & 0x01 != 0;
+        let mut payload_start = 4;
+        let mut pcr = None;
+        if afc & 0x02 != 0 {
+            let len = p[4] as usize;
+            if len > 0 && p[5] & 0x10 != 0 && len >= 7 {
+                pcr = Some(6);
+            }
+            payload_start = 5 + len;
+        }
+        let (mut pts, mut dts) = (None, None);
+        if has_payload && pusi && payload_start + 19 <= PACKET_SIZE {
+            let pes = &p[payload_start..];
+            let stream_id = pes[3];
+            let has_header = !matches!(
+                stream_id,
+                0xBC | 0xBE | 0xBF | 0xF0 | 0xF1 | 0xF2 | 0xF8 | 0xFF
+            );
+            if pes[..3] == [0, 0, 1] && has_header {
+                match pes[7] >> 6 {
+                    0b10 => pts = Some(payload_start + 9),
+                    0b11 => {
+                        pts = Some(payload_start + 9);
+                        dts = Some(payload_start + 14);
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Packet {
+            pid,
+            has_payload,
+            pcr,
+            pts,
+            dts,
+        }
+    }
+}
+
+/// 将分片切分为 188 字节的包，跳过无法同步的字节。返回包与跳过的字节数。
+fn split_packets(data: &[u8]) -> (Vec<&[u8]>, u64) {
+    let mut packets = Vec::with_capacity(data.len() / PACKET_SIZE);
+    let mut skipped = 0;
+    let mut i = 0;
+    while i + PACKET_SIZE <= data.len() {
+        if data[i] == SYNC_BYTE
+            && (i + PACKET_SIZE == data.len() || data[i + PACKET_SIZE] == SYNC_BYTE)
+        {
+            packets.push(&data[i..i + PACKET_SIZE]);
+            i += PACKET_SIZE;
+        } else {
+            i += 1;
+            skipped += 1;
+        }
+    }
+    skipped += (data.len() - i) as u64;
+    (packets, skipped)
+}
+
+/// 拼接器，按顺序写入各分片。
+pub struct TsConcatenator<W: Write> {
+    out: W,
+    options: ConcatOptions,
+    continuity: HashMap<u16, u8>,
+    offset: i64,
+    last_timestamp: Option<i64>,
+    stats: ConcatStats,
+}
+impl<W: Write> TsConcatenator<W> {
+    pub fn new(out: W, options: ConcatOptions) -> Self {
+        Self {
+            out,
+            options,
+            continuity: HashMap::new(),
+            offset: 0,
+            last_timestamp: None,
+            stats: ConcatStats::default(),
+        }
+    }
+    /// 写入一个分片，`discontinuity` 表示该分片前是否有 `EXT-X-DISCONTINUITY` 标签。
+    pub fn push(&mut self, data: &[u8], discontinuity: bool) -> std::io::Result<()> {
+        let (packets, skipped) = split_packets(data);
+        if skipped > 0 {
+            warn!("分片中有 {skipped} 字节无法同步，已丢弃。");
+        }
+        self.stats.skipped_bytes += skipped;
+        let parsed = packets.iter().map(|p| Packet::parse(p)).collect::<Vec<_>>();
+        if self.options.rewrite_timestamps {
+            self.update_offset(&packets, &parsed, discontinuity);
+        }
+        let mut buf = [0u8; PACKET_SIZE];
+        for (p, info) in packets.iter().zip(parsed) {
+            buf.copy_from_slice(p);
+            // 空包（PID 0x1FFF）的计数器无意义。
+            if info.pid != 0x1FFF {
+                let cc = self.continuity.entry(info.pid).or_insert(0x0F);
+                if info.has_payload {
+                    *cc = (*cc + 1) & 0x0F;
+                }
+                buf[3] = (buf[3] & 0xF0) | *cc;
+            }
+            if self.options.rewrite_timestamps {
+                if let Some(i) = info.pcr {
+                    let base = shift(read_pcr_base(&buf[i..]), self.offset);
+                    write_pcr_base(&mut buf[i..], base);
+                }
+                for i in [info.pts, info.dts].into_iter().flatten() {
+                    let ts = shift(read_timestamp(&buf[i..]), self.offset);
+                    write_timestamp(&mut buf[i..], ts);
+                    self.last_timestamp = Some(match self.last_timestamp {
+                        Some(last) if timestamp_diff(last, ts) > 0 => last,
+                        _ => ts,
+                    });
+                }
+            }
+            self.out.write_all(&buf)?;
+            self.stats.packets += 1;
+        }
+        self.stats.segments += 1;
+        Ok(())
+    }
+    /// 在不连续处（或时间戳明显回退处）重新计算偏移量，使该分片紧接上一分片。
+    fn update_offset(&mut self, packets: &[&[u8]], parsed: &[Packet], discontinuity: bool) {
+        let Some(last) = self.last_timestamp else {
+            return;
+        };
+        let first = packets
+            .iter()
+            .zip(parsed)
+            .filter_map(|(p, info)| info.dts.or(info.pts).map(|i| read_timestamp(&p[i..])))
+            .reduce(|a, b| if timestamp_diff(a, b) > 0 { b } else { a });
+        let Some(first) = first else {
+            return;
+        };
+        let jump = timestamp_diff(shift(first, self.offset), last);
+        if discontinuity || jump < -BACKWARD_JUMP_THRESHOLD {
+            self.offset = timestamp_diff(last + DISCONTINUITY_GAP, first);
+            self.stats.rewritten_boundaries += 1;
+            debug!(
+                "ts: 第 {} 个分片处改写时间戳，偏移量 {}.",
+                self.stats.segments, self.offset
+            );
+        }
+    }
+    pub fn finish(mut self) -> std::io::Result<ConcatStats> {
+        self.out.flush()?;
+        Ok(self.stats)
+    }
+}
+
+/// 按顺序拼接 `inputs` 中的分片文件并写入 `output`.
+///
+/// `inputs` 中每项的第二个值表示该分片前是否不连续。
+pub fn concat_files(
+    inputs: &[(PathBuf, bool)],
+    output: &Path,
+    options: ConcatOptions,
+) -> std::io::Result<ConcatStats> {
+    let out = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let mut concatenator = TsConcatenator::new(out, options);
+    let mut data = Vec::new();
+    for (path, discontinuity) in inputs {
+        data.clear();
+        std::fs::File::open(path)?.read_to_end(&mut data)?;
+        concatenator.push(&data, *discontinuity)?;
+    }
+    concatenator.finish()
+}
+/// 拼接 `dir` 中由 [`crate::download::download_stream`] 下载完成的分片。
+#[cfg(feature = "download")]
+pub fn concat_download(
+    dir: &Path,
+    output: &Path,
+    options: ConcatOptions,
+) -> std::io::Result<ConcatStats> {
+    let state = crate::download::DownloadState::load(dir).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "没有找到下载进度记录。")
+    })?;
+    if !state.is_complete() {
+        warn!("下载尚未完成，仅拼接已下载的分片。");
+    }
+    let inputs = state
+        .segments()
+        .iter()
+        .filter(|s| s.done())
+        .map(|s| (dir.join(s.file()), s.discontinuity()))
+        .collect::<Vec<_>>();
+    concat_files(&inputs, output, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pes_packet(pid: u16, cc: u8, pts: i64) -> Vec<u8> {
+        let mut p = vec![0xFF; PACKET_SIZE];
+        p[0] = SYNC_BYTE;
+        p[1] = 0x40 | (pid >> 8) as u8;
+        p[2] = pid as u8;
+        p[3] = 0x10 | cc;
+        p[4..13].copy_from_slice(&[0, 0, 1, 0xE0, 0, 0, 0x80, 0x80, 5]);
+        p[13] = 0x21;
+        write_timestamp(&mut p[13..], pts);
+        p
+    }
+    fn output_pts(data: &[u8]) -> Vec<(u8, i64)> {
+        data.chunks(PACKET_SIZE)
+            .map(|p| (p[3] & 0x0F, read_timestamp(&p[13..])))
+            .collect()
+    }
+
+    #[test]
+    fn test_timestamp_roundtrip() {
+        let mut b = [0x21, 0, 0, 0, 0];
+        for ts in [0, 1, 90000, TIMESTAMP_MODULUS - 1] {
+            write_timestamp(&mut b, ts);
+            assert_eq!(read_timestamp(&b), ts);
+        }
+    }
+    #[test]
+    fn test_concat_discontinuity() {
+        let seg1 = [pes_packet(0x100, 5, 900000), pes_packet(0x100, 6, 903600)].concat();
+        let seg2 = [pes_packet(0x100, 0, 1000), pes_packet(0x100, 1, 4600)].concat();
+        let mut out = Vec::new();
+        let mut c = TsConcatenator::new(
+            &mut out,
+            ConcatOptions {
+                rewrite_timestamps: true,
+            },
+        );
+        c.push(&seg1, false).unwrap();
+        c.push(&seg2, true).unwrap();
+        let stats = c.finish().unwrap();
+        assert_eq!(stats.packets, 4);
+        assert_eq!(stats.rewritten_boundaries, 1);
+        assert_eq!(
+            output_pts(&out),
+            [(0, 900000), (1, 903600), (2, 907200), (3, 910800)]
+        );
+    }
+    #[test]
+    fn test_concat_resync() {
+        let data = [vec![0u8; 3], pes_packet(0x100, 0, 0)].concat();
+        let mut out = Vec::new();
+        let mut c = TsConcatenator::new(&mut out, ConcatOptions::default());
+        c.push(&data, false).unwrap();
+        let stats = c.finish().unwrap();
+        assert_eq!(stats.skipped_bytes, 3);
+        assert_eq!(out.len(), PACKET_SIZE);
+    }
+}